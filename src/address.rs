@@ -0,0 +1,339 @@
+use crate::parser::{unquote,strip_comments,decode_encoded_words};
+
+///a single rfc 5322 mailbox, the local part is stored without quotes and the domain as written (unicode domains are kept).
+#[derive(Debug,Clone,PartialEq)]
+pub struct Mailbox{
+    pub name:Option<String>,
+    pub local:String,
+    pub domain:String,
+    pub group:Option<String>
+}
+
+impl Mailbox{
+    ///returns the addr-spec, the local part is quoted again if it needs to be.
+    pub fn address(&self)->String{
+        let atext = self.local.split('.').all(|atom|{
+            !atom.is_empty() && atom.chars().all(|c|{
+                c.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+            })
+        });
+        if atext{
+            return format!("{}@{}",self.local,self.domain);
+        }
+        let escaped = self.local.replace('\\',"\\\\").replace('"',"\\\"");
+        format!("\"{}\"@{}",escaped,self.domain)
+    }
+    ///punycode form of the domain for dns lookups, domain literals are returned as is.
+    pub fn ascii_domain(&self)->Result<String,&'static str>{
+        if self.domain.starts_with('['){
+            return Ok(self.domain.clone());
+        }
+        idna::domain_to_ascii(&self.domain).map_err(|_|"invalid-idn_domain")
+    }
+    ///unicode form of the domain, punycode labels are decoded.
+    pub fn unicode_domain(&self)->String{
+        if self.domain.starts_with('['){
+            return self.domain.clone();
+        }
+        idna::domain_to_unicode(&self.domain).0
+    }
+}
+
+///parses an rfc 5322 address-list, mailboxes that cannot be parsed are skipped.
+///
+///display names (including rfc 2047 encoded-words), quoted local parts, comments, obsolete routes and
+///groups are supported, members of a group carry the group name and empty groups yield nothing.
+pub fn parse_address_list(input:&str)->Vec<Mailbox>{
+
+    let mut collect:Vec<Mailbox> = vec![];
+    let mut current = String::new();
+    let mut group:Option<String> = None;
+    let mut in_quote = false;
+    let mut in_angle = false;
+    let mut in_literal = false;
+    let mut escaped = false;
+    let mut comment_depth:usize = 0;
+
+    for c in input.chars(){
+        if escaped{
+            escaped = false;
+            current.push(c);
+            continue;
+        }
+        if c == '\\' && (in_quote || comment_depth > 0){
+            escaped = true;
+            current.push(c);
+            continue;
+        }
+        if in_quote{
+            if c == '"'{in_quote = false;}
+            current.push(c);
+            continue;
+        }
+        if comment_depth > 0{
+            if c == '('{comment_depth += 1;} else if c == ')'{comment_depth -= 1;}
+            current.push(c);
+            continue;
+        }
+        match c{
+            '"'=>{in_quote = true;},
+            '('=>{comment_depth = 1;},
+            '<'=>{in_angle = true;},
+            '>'=>{in_angle = false;},
+            '['=>{in_literal = true;},
+            ']'=>{in_literal = false;},
+            ':' if !in_angle && !in_literal && group.is_none()=>{
+                group = Some(display_name(&current).unwrap_or_default());
+                current = String::new();
+                continue;
+            },
+            ';' if !in_angle && group.is_some()=>{
+                if let Some(v) = parse_mailbox(&current,&group){collect.push(v);}
+                current = String::new();
+                group = None;
+                continue;
+            },
+            ',' if !in_angle=>{
+                if let Some(v) = parse_mailbox(&current,&group){collect.push(v);}
+                current = String::new();
+                continue;
+            },
+            _=>{}
+        }
+        current.push(c);
+    }
+
+    if let Some(v) = parse_mailbox(&current,&group){collect.push(v);}
+
+    collect
+
+}
+
+///parses a single mailbox like `Name <local@domain>` or `local@domain (Name)`.
+pub fn parse_mailbox(input:&str,group:&Option<String>)->Option<Mailbox>{
+
+    let comment = last_comment(input);
+    let clean = strip_comments(input);
+    let clean = clean.trim();
+    if clean.is_empty(){
+        return None;
+    }
+
+    let name:Option<String>;
+    let addr_spec:&str;
+    match find_unquoted(clean,'<'){
+        Some(start)=>{
+            name = display_name(&clean[..start]);
+            let rest = &clean[start+1..];
+            match find_unquoted(rest,'>'){
+                Some(end)=>{addr_spec = &rest[..end];},
+                None=>{addr_spec = rest;}
+            }
+        },
+        None=>{
+            name = comment;
+            addr_spec = clean;
+        }
+    }
+
+    //obsolete source routes look like <@relay.example:local@domain>
+    let mut addr_spec = addr_spec.trim();
+    if addr_spec.starts_with('@'){
+        if let Some(v) = find_unquoted(addr_spec,':'){addr_spec = addr_spec[v+1..].trim();}
+    }
+
+    let at = rfind_unquoted(addr_spec,'@')?;
+    let local = addr_spec[..at].trim();
+    let domain = addr_spec[at+1..].trim();
+    if local.is_empty() || domain.is_empty(){
+        return None;
+    }
+
+    let local = if local.starts_with('"'){
+        unquote(local)
+    } else {
+        local.split('.').map(|atom|atom.trim()).collect::<Vec<&str>>().join(".")
+    };
+    let domain:String = domain.chars().filter(|c|!c.is_whitespace()).collect();
+    if domain.contains('@') || domain.contains('"') || domain.contains(','){
+        return None;
+    }
+
+    Some(Mailbox{
+        name,
+        local,
+        domain,
+        group:group.clone()
+    })
+
+}
+
+fn display_name(input:&str)->Option<String>{
+    let mut words:Vec<String> = vec![];
+    let mut current = String::new();
+    let mut in_quote = false;
+    let mut escaped = false;
+    for c in strip_comments(input).chars(){
+        if escaped{
+            current.push(c);
+            escaped = false;
+        } else if in_quote{
+            if c == '\\'{escaped = true;} else if c == '"'{in_quote = false;}
+            else {current.push(c);}
+        } else if c == '"'{
+            in_quote = true;
+        } else if c.is_whitespace(){
+            if !current.is_empty(){
+                words.push(current);
+                current = String::new();
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty(){
+        words.push(current);
+    }
+    let name = decode_encoded_words(&words.join(" "));
+    if name.trim().is_empty(){
+        return None;
+    }
+    Some(name.trim().to_string())
+}
+
+fn last_comment(input:&str)->Option<String>{
+    let mut collect:Option<String> = None;
+    let mut current = String::new();
+    let mut in_quote = false;
+    let mut escaped = false;
+    let mut comment_depth:usize = 0;
+    for c in input.chars(){
+        if escaped{
+            escaped = false;
+            if comment_depth > 0{current.push(c);}
+            continue;
+        }
+        if c == '\\' && (in_quote || comment_depth > 0){
+            escaped = true;
+            continue;
+        }
+        if comment_depth > 0{
+            if c == '('{comment_depth += 1;} else if c == ')'{
+                comment_depth -= 1;
+                if comment_depth == 0{
+                    if !current.trim().is_empty(){
+                        collect = Some(decode_encoded_words(current.trim()));
+                    }
+                    current = String::new();
+                    continue;
+                }
+            }
+            current.push(c);
+        } else if in_quote{
+            if c == '"'{in_quote = false;}
+        } else if c == '"'{
+            in_quote = true;
+        } else if c == '('{
+            comment_depth = 1;
+        }
+    }
+    collect
+}
+
+fn find_unquoted(input:&str,target:char)->Option<usize>{
+    let mut in_quote = false;
+    let mut escaped = false;
+    for (index,c) in input.char_indices(){
+        if escaped{
+            escaped = false;
+        } else if in_quote{
+            if c == '\\'{escaped = true;} else if c == '"'{in_quote = false;}
+        } else if c == '"'{
+            in_quote = true;
+        } else if c == target{
+            return Some(index);
+        }
+    }
+    None
+}
+
+fn rfind_unquoted(input:&str,target:char)->Option<usize>{
+    let mut found:Option<usize> = None;
+    let mut in_quote = false;
+    let mut escaped = false;
+    for (index,c) in input.char_indices(){
+        if escaped{
+            escaped = false;
+        } else if in_quote{
+            if c == '\\'{escaped = true;} else if c == '"'{in_quote = false;}
+        } else if c == '"'{
+            in_quote = true;
+        } else if c == target{
+            found = Some(index);
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{Mailbox,parse_address_list};
+    use crate::{Config,parse_bytes};
+
+    fn mailbox(name:Option<&str>,local:&str,domain:&str,group:Option<&str>)->Mailbox{
+        Mailbox{
+            name:name.map(|v|v.to_string()),
+            local:local.to_string(),
+            domain:domain.to_string(),
+            group:group.map(|v|v.to_string())
+        }
+    }
+
+    #[test]
+    fn quoted_names(){
+        let list = parse_address_list("\"Doe, John\" <john@example.com>, \"a\\\"b\" <\"x y\"@my-host.example>");
+        assert_eq!(list,vec![
+            mailbox(Some("Doe, John"),"john","example.com",None),
+            mailbox(Some("a\"b"),"x y","my-host.example",None)
+        ]);
+        assert_eq!(list[1].address(),"\"x y\"@my-host.example");
+    }
+
+    #[test]
+    fn groups(){
+        assert_eq!(parse_address_list("undisclosed-recipients:;"),vec![]);
+        let list = parse_address_list("Team: a@x.org, B <b@x.org>;, c@y.org");
+        assert_eq!(list,vec![
+            mailbox(None,"a","x.org",Some("Team")),
+            mailbox(Some("B"),"b","x.org",Some("Team")),
+            mailbox(None,"c","y.org",None)
+        ]);
+    }
+
+    #[test]
+    fn comments(){
+        let list = parse_address_list("john@example.com (John Doe), Jane (work) <jane(at)@example.com>");
+        assert_eq!(list,vec![
+            mailbox(Some("John Doe"),"john","example.com",None),
+            mailbox(Some("Jane"),"jane","example.com",None)
+        ]);
+    }
+
+    #[test]
+    fn encoded_names(){
+        let list = parse_address_list("=?utf-8?Q?Andr=C3=A9?= <andre@b\u{fc}cher.example>");
+        assert_eq!(list,vec![mailbox(Some("Andr\u{e9}"),"andre","b\u{fc}cher.example",None)]);
+        assert_eq!(list[0].ascii_domain().unwrap(),"xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn headers(){
+        let raw = "From: \"Doe, John\" <john@example.com>\r\nTo: a@x.org, b@x.org\r\nCc: undisclosed-recipients:;\r\n\r\nhi\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        assert_eq!(email.from,vec![mailbox(Some("Doe, John"),"john","example.com",None)]);
+        assert_eq!(email.to.len(),2);
+        assert!(email.cc.is_empty());
+    }
+
+}
@@ -3,32 +3,40 @@ use std::collections::HashMap;
 use regex::Regex;
 use crate::part::init as PartParser;
 use crate::dkim::init as DkimInit;
+use crate::parser::parse_params;
+use crate::address::{Mailbox,parse_address_list};
+use crate::date::EmailDate;
+use crate::received::{Hop,hop_chain};
+use crate::mime::{MimeNode,PartRef};
 use trust_dns_resolver::config::{ResolverConfig,ResolverOpts};
 use trust_dns_resolver::{AsyncResolver,TokioConnection,TokioConnectionProvider};
 use openssl::pkey::{PKey,Public};
 use std::sync::Arc;
+use std::borrow::Cow;
+use crate::flowed;
+use crate::html;
+use crate::sanitize::rewrite_cids;
+use crate::digest::{Digest,AttachmentSummary};
 
 #[derive(Debug,Clone)]
 pub struct Config{
     pub keys:Arc<TokioRwLock<HashMap<String,PKey<Public>>>>,
-    pub boundary_regex:Regex,
     pub keyval_regex:Regex,
-    pub feature_regex:Regex,
-    pub from_regex:Regex,
-    pub resolver:AsyncResolver<TokioConnection,TokioConnectionProvider>
+    pub resolver:AsyncResolver<TokioConnection,TokioConnectionProvider>,
+    ///how deep attached message/rfc822 parts are parsed into nested emails, 0 disables it.
+    pub max_nesting:usize,
+    ///also validate the dkim signatures of nested emails when validating.
+    pub validate_nested:bool,
+    ///the input is smtp DATA, leading dots are unstuffed and the "." end line is stripped before parsing.
+    pub dot_stuffed:bool,
+    ///malformed headers and parts are skipped or decoded leniently and reported in `EmailBody::warnings` instead of failing the parse.
+    pub lenient:bool
 }
 
 impl Config{
+    #[allow(clippy::needless_late_init,clippy::needless_return,clippy::redundant_field_names)]
     pub fn new()->Result<Config,&'static str>{
 
-        let boundary_regex:Regex;
-        match Regex::new(r"--([\w\d]+)([--]*)"){
-            Ok(v)=>{boundary_regex = v;},
-            Err(_)=>{
-                return Err("failed-regex-boundary_regex");
-            }
-        }
-
         let keyval_regex:Regex;
         match Regex::new(r"([\w\d-]+)\s*:\s*([\w\d\W]+)"){
             Ok(v)=>{keyval_regex = v;},
@@ -37,22 +45,6 @@ impl Config{
             }
         }
 
-        let feature_regex:Regex;
-        match Regex::new(r#"([\w\d-]+)="*([\w\s\d\D]+)"*"#){
-            Ok(v)=>{feature_regex = v;},
-            Err(_)=>{
-                return Err("failed-regex-feature_regex");
-            }
-        }
-
-        let from_regex:Regex;
-        match Regex::new(r#"([\w\d_=+/*!@#$%^&*()-|]+)@([\w\d.]+)"#){
-            Ok(v)=>{from_regex = v;},
-            Err(_)=>{
-                return Err("failed-regex-from_regex");
-            }
-        }
-
         let resolver:AsyncResolver<TokioConnection,TokioConnectionProvider>;
         match AsyncResolver::tokio(ResolverConfig::default(),ResolverOpts::default()){
             Ok(v)=>{resolver = v;},
@@ -63,11 +55,12 @@ impl Config{
 
         return Ok(Config{
             keys:Arc::new(TokioRwLock::new(HashMap::new())),
-            boundary_regex:boundary_regex,
             keyval_regex:keyval_regex,
-            feature_regex:feature_regex,
-            from_regex:from_regex,
-            resolver:resolver
+            resolver:resolver,
+            max_nesting:8,
+            validate_nested:false,
+            dot_stuffed:false,
+            lenient:false
         });
     }
 }
@@ -90,27 +83,143 @@ impl Dkim{
         self.features = features;
         self.order.append(&mut order);
     }
+    ///signature timestamp from the t= tag.
+    pub fn timestamp(&self)->Option<i64>{
+        match self.features.get("t"){
+            Some(v)=>{v.trim().parse::<i64>().ok()},
+            None=>{None}
+        }
+    }
+}
+
+///line ending style of the input, dkim canonicalization and re-serialization need crlf.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum LineEnding{
+    Crlf,Lf,Mixed,None
+}
+
+impl LineEnding{
+    pub fn from_counts(crlf:usize,lf:usize)->LineEnding{
+        if crlf > 0 && lf > 0{return LineEnding::Mixed;}
+        if crlf > 0{return LineEnding::Crlf;}
+        if lf > 0{return LineEnding::Lf;}
+        LineEnding::None
+    }
+    ///converts bare lf line endings in data taken from the input to crlf, crlf input is borrowed as is.
+    pub fn to_crlf<'a>(&self,data:&'a [u8])->Cow<'a,[u8]>{
+        match self{
+            LineEnding::Crlf|LineEnding::None=>{return Cow::Borrowed(data);},
+            _=>{}
+        }
+        let mut collect:Vec<u8> = Vec::with_capacity(data.len() + data.len() / 32);
+        let mut previous:u8 = 0;
+        for b in data.iter(){
+            if *b == b'\n' && previous != b'\r'{
+                collect.push(b'\r');
+            }
+            collect.push(*b);
+            previous = *b;
+        }
+        Cow::Owned(collect)
+    }
 }
 
 #[derive(Debug,Clone)]
 pub enum ContentEncoding{
-    Base64,Qp,String,UnSupported
+    Base64,Qp,String,EightBit,Binary,UnSupported
 }
 
 #[derive(Debug,Clone)]
 pub enum ContentDecoded{
-    Base64(Vec<u8>),Qp(Vec<u8>),String(String),Html(String),None
+    Base64(Vec<u8>),Qp(Vec<u8>),String(String),Html(String),
+    ///raw bytes of an 8bit or binary part that is not text.
+    Binary(Vec<u8>),
+    None
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum DispositionKind{
+    Inline,Attachment,Other(String),None
+}
+
+#[derive(Debug,Clone)]
+pub struct ContentDisposition{
+    pub kind:DispositionKind,
+    pub filename:Option<String>,
+    pub size:Option<usize>,
+    pub creation_date:Option<String>,
+    pub modification_date:Option<String>,
+    pub read_date:Option<String>,
+    pub params:HashMap<String,String>
+}
+
+impl Default for ContentDisposition{
+    fn default()->ContentDisposition{
+        ContentDisposition::new()
+    }
+}
+
+impl ContentDisposition{
+    pub fn new()->ContentDisposition{
+        ContentDisposition{
+            kind:DispositionKind::None,
+            filename:None,
+            size:None,
+            creation_date:None,
+            modification_date:None,
+            read_date:None,
+            params:HashMap::new()
+        }
+    }
+    ///parses the value of a Content-Disposition header as described in rfc 2183.
+    pub fn parse(value:&str)->ContentDisposition{
+        let (kind,params,_) = parse_params(value);
+        let mut disposition = ContentDisposition::new();
+        disposition.kind = match kind.as_str(){
+            "inline"=>DispositionKind::Inline,
+            "attachment"=>DispositionKind::Attachment,
+            ""=>DispositionKind::None,
+            _=>DispositionKind::Other(kind)
+        };
+        disposition.filename = params.get("filename").cloned();
+        if let Some(v) = params.get("size"){disposition.size = v.trim().parse::<usize>().ok();}
+        disposition.creation_date = params.get("creation-date").cloned();
+        disposition.modification_date = params.get("modification-date").cloned();
+        disposition.read_date = params.get("read-date").cloned();
+        disposition.params = params;
+        disposition
+    }
 }
 
 #[derive(Debug,Clone)]
 pub struct Part{
     pub content_type:(String,HashMap<String,String>,Vec<String>),
     pub content_features:HashMap<String,String>,
-    pub data:String,
-    pub decoded:ContentDecoded
+    pub disposition:ContentDisposition,
+    ///the undecoded body exactly as in the input, it is emptied once decoded.
+    pub data:Vec<u8>,
+    ///byte range of the body in the input.
+    pub offsets:(usize,usize),
+    pub decoded:ContentDecoded,
+    ///the parsed email of a message/rfc822, message/global or text/rfc822-headers part.
+    pub message:Option<Box<EmailBody>>,
+    ///why the part could not be decoded strictly, only set in lenient mode.
+    pub error:Option<&'static str>,
+    ///decoded bytes handed to an attachment sink, such parts keep no data.
+    pub written:Option<u64>,
+    ///the text could not be converted from its charset without replacements or the charset had to be guessed.
+    pub lossy:bool,
+    ///the type detected from the magic bytes of the decoded body, see `sniff::sniff`.
+    pub sniffed_type:Option<&'static str>,
+    ///the detected type does not fit the declared content type.
+    pub type_mismatch:bool,
+    ///digests of the body after its transfer encoding is removed and before any charset or line ending conversion,
+    ///so they match the file as sent.
+    pub digest:Option<Digest>
 }
 
 impl Part{
+    #[allow(clippy::new_without_default)]
     pub fn new()->Part{
         Part{
             content_type:(
@@ -119,8 +228,17 @@ impl Part{
                 Vec::new()
             ),
             content_features:HashMap::new(),
-            data:String::new(),
-            decoded:ContentDecoded::None
+            disposition:ContentDisposition::new(),
+            data:Vec::new(),
+            offsets:(0,0),
+            decoded:ContentDecoded::None,
+            message:None,
+            error:None,
+            written:None,
+            lossy:false,
+            sniffed_type:None,
+            type_mismatch:false,
+            digest:None
         }
     }
     pub fn reset(&mut self){
@@ -130,15 +248,122 @@ impl Part{
             Vec::new()
         );
         self.content_features = HashMap::new();
-        self.data = String::new();
+        self.disposition = ContentDisposition::new();
+        self.data = Vec::new();
+        self.offsets = (0,0);
         self.decoded = ContentDecoded::None;
+        self.message = None;
+        self.error = None;
+        self.written = None;
+        self.lossy = false;
+        self.sniffed_type = None;
+        self.type_mismatch = false;
+        self.digest = None;
+    }
+    ///case insensitive lookup of a part header.
+    pub fn feature(&self,key:&str)->Option<&String>{
+        for (k,v) in self.content_features.iter(){
+            if k.eq_ignore_ascii_case(key){
+                return Some(v);
+            }
+        }
+        None
+    }
+    ///true for parts that carry a whole email or its headers.
+    pub fn is_message(&self)->bool{
+        let content_type = self.content_type.0.as_str();
+        content_type == "message/rfc822" ||
+            content_type == "message/global" ||
+            content_type == "text/rfc822-headers" ||
+            content_type == "message/global-headers"
+    }
+    ///filename from Content-Disposition, falls back to the name parameter of Content-Type.
+    pub fn filename(&self)->Option<&String>{
+        if let Some(v) = &self.disposition.filename{return Some(v);}
+        self.content_type.1.get("name")
+    }
+    ///the Content-ID without angle brackets and whitespace.
+    pub fn content_id(&self)->Option<String>{
+        let value = self.feature("Content-ID")?;
+        let id:String = value.chars().filter(|c|!c.is_whitespace()).collect();
+        let id = id.trim_start_matches('<').trim_end_matches('>');
+        if id.is_empty(){
+            return None;
+        }
+        Some(id.to_string())
+    }
+    ///the Content-Location with folding whitespace removed.
+    pub fn content_location(&self)->Option<String>{
+        let value = self.feature("Content-Location")?;
+        let location:String = value.chars().filter(|c|!c.is_whitespace()).collect();
+        if location.is_empty(){
+            return None;
+        }
+        Some(location)
+    }
+    ///decoded bytes of the part, text is returned as utf-8.
+    pub fn bytes(&self)->Option<&[u8]>{
+        match &self.decoded{
+            ContentDecoded::Base64(v)|ContentDecoded::Qp(v)|ContentDecoded::Binary(v)=>{Some(v)},
+            ContentDecoded::String(v)|ContentDecoded::Html(v)=>{Some(v.as_bytes())},
+            ContentDecoded::None=>{None}
+        }
+    }
+    ///sha-256, md5 and size of the transfer decoded body, see `Part::digest`.
+    pub fn digest(&self)->Option<Digest>{
+        self.digest.clone()
+    }
+    ///the part as a data: uri, text parts are labeled utf-8.
+    pub fn data_uri(&self)->Option<String>{
+        let bytes = self.bytes()?;
+        let mut content_type = self.content_type.0.clone();
+        if content_type.is_empty(){
+            content_type = "application/octet-stream".to_string();
+        }
+        match &self.decoded{
+            ContentDecoded::String(_)|ContentDecoded::Html(_)=>{content_type.push_str(";charset=utf-8");},
+            _=>{}
+        }
+        Some(format!("data:{};base64,{}",content_type,base64::encode(bytes)))
+    }
+    ///true for text sent as format=flowed (rfc 3676).
+    pub fn is_flowed(&self)->bool{
+        match self.content_type.1.get("format"){
+            Some(v)=>{v.eq_ignore_ascii_case("flowed")},
+            None=>{false}
+        }
+    }
+    ///the decoded text with flowed paragraphs joined, text that is not flowed is returned as is.
+    pub fn unflowed(&self)->Option<String>{
+        let text = match &self.decoded{
+            ContentDecoded::String(v)|ContentDecoded::Html(v)=>{v},
+            _=>{return None;}
+        };
+        if !self.is_flowed(){
+            return Some(text.clone());
+        }
+        let delsp = match self.content_type.1.get("delsp"){
+            Some(v)=>{v.eq_ignore_ascii_case("yes")},
+            None=>{false}
+        };
+        Some(flowed::decode(text,delsp))
     }
 }
 
 #[derive(Debug,Clone)]
 pub struct PartHandler{
     finished:Vec<Part>,
-    active:Part
+    active:Part,
+    started:bool,
+    pending:Vec<u8>,
+    retain:bool,
+    skip:bool
+}
+
+impl Default for PartHandler{
+    fn default()->PartHandler{
+        PartHandler::new()
+    }
 }
 
 impl PartHandler{
@@ -146,28 +371,85 @@ impl PartHandler{
     pub fn content_feature(&mut self,key:String,value:String){
         self.active.content_features.insert(key,value);
     }
-    pub fn data(&mut self,v:String){
-        // println!("\n\n{:?}\n\n",v);
-        if self.active.data.len() == 0{
-            self.active.data.push_str(&v);
-        } else {
-            self.active.data.push_str("\n");
-            self.active.data.push_str(&v);
+    ///adds a body line, the ending of the last line is left out as it belongs to the following boundary.
+    pub fn data(&mut self,line:&[u8],ending:&[u8],offset:usize){
+        if !self.started{
+            self.started = true;
+            self.active.offsets = (offset,offset);
+        } else if self.retain && !self.skip{
+            self.active.data.extend_from_slice(&self.pending);
         }
-        // self.active.data.push_str(&v);
+        if self.retain && !self.skip{
+            self.active.data.extend_from_slice(line);
+        }
+        self.active.offsets.1 = offset + line.len();
+        self.pending.clear();
+        self.pending.extend_from_slice(ending);
     }
     pub fn new()->PartHandler{
         PartHandler{
             finished:Vec::new(),
-            active:Part::new()
+            active:Part::new(),
+            started:false,
+            pending:Vec::new(),
+            retain:true,
+            skip:false
         }
     }
+    ///when false part bodies are not kept, only their offsets, used by streaming consumers that handle the data themselves.
+    pub fn retain(&mut self,v:bool){
+        self.retain = v;
+    }
+    ///drops the data of a part that is handed to an attachment sink and stops collecting it if it is still active.
+    pub fn release(&mut self,index:usize){
+        let part = if index == self.finished.len(){
+            self.skip = true;
+            &mut self.active
+        } else {
+            match self.finished.get_mut(index){
+                Some(v)=>{v},
+                None=>{return;}
+            }
+        };
+        part.data = Vec::new();
+        part.written = Some(0);
+    }
+    ///index the active part will get once finished.
+    pub fn next_index(&self)->usize{
+        self.finished.len()
+    }
     pub fn flush(&mut self){
-        if self.active.data.len() > 0 {
+        if !self.active.data.is_empty(){
             self.finished.push(self.active.clone());
         }
+        self.discard();
+    }
+    ///finishes the active part even if it has no data and returns its index.
+    pub fn finish(&mut self)->usize{
+        let mut part = Part::new();
+        std::mem::swap(&mut part,&mut self.active);
+        self.finished.push(part);
+        self.discard();
+        self.finished.len() - 1
+    }
+    ///drops the active part, used when its headers turn out to belong to a multipart entity.
+    pub fn discard(&mut self){
         self.active.reset();
+        self.started = false;
+        self.skip = false;
+        self.pending.clear();
     }
+    pub fn active(&self)->&Part{
+        &self.active
+    }
+}
+
+///a multipart/related group, the root is the start part (usually the html) and the resources are the
+///other parts keyed by Content-ID and Content-Location.
+#[derive(Debug,Clone)]
+pub struct Related<'a>{
+    pub root:Option<&'a Part>,
+    pub resources:HashMap<String,&'a Part>
 }
 
 #[derive(Debug,Clone)]
@@ -175,18 +457,35 @@ pub struct EmailBody{
     pub dkim_found:bool,
     pub dkim:Dkim,
     pub headers:HashMap<String,String>,
+    pub header_list:Vec<(String,String)>,
     pub parts:Vec<Part>,
     pub body:Vec<Part>,
     pub attachments:Vec<Part>,
     pub content_type:(String,HashMap<String,String>,Vec<String>),
+    pub from:Vec<Mailbox>,
+    pub sender:Option<Mailbox>,
+    pub reply_to:Vec<Mailbox>,
+    pub to:Vec<Mailbox>,
+    pub cc:Vec<Mailbox>,
+    pub bcc:Vec<Mailbox>,
+    pub date:Option<EmailDate>,
+    pub received:Vec<Hop>,
+    pub tree:MimeNode,
+    ///byte offset of the body (after the header block) in the input.
+    pub body_offset:usize,
+    pub line_ending:LineEnding,
+    ///problems skipped over in lenient mode.
+    pub warnings:Vec<&'static str>,
 }
 
 impl EmailBody{
+    #[allow(clippy::new_without_default)]
     pub fn new()->EmailBody{
         EmailBody{
             dkim_found:false,
             dkim:Dkim::init(),
             headers:HashMap::new(),
+            header_list:Vec::new(),
             parts:Vec::new(),
             body:Vec::new(),
             attachments:Vec::new(),
@@ -195,21 +494,83 @@ impl EmailBody{
                 HashMap::new(),
                 Vec::new()
             ),
+            from:Vec::new(),
+            sender:None,
+            reply_to:Vec::new(),
+            to:Vec::new(),
+            cc:Vec::new(),
+            bcc:Vec::new(),
+            date:None,
+            received:Vec::new(),
+            tree:MimeNode::new((String::new(),HashMap::new(),Vec::new()),None,0),
+            body_offset:0,
+            line_ending:LineEnding::None,
+            warnings:Vec::new(),
         }
     }
     pub fn header(&mut self,key:String,value:String){
+        self.header_list.push((key.clone(),value.clone()));
         self.headers.insert(key.to_lowercase(),value);
     }
+    ///every value of a repeated header in message order, the headers map only keeps the last one.
+    pub fn header_all(&self,key:&str)->Vec<&String>{
+        let mut collect = vec![];
+        for (k,v) in self.header_list.iter(){
+            if k.eq_ignore_ascii_case(key){
+                collect.push(v);
+            }
+        }
+        collect
+    }
+    ///parses the address headers into mailbox lists.
+    pub fn addresses(&mut self){
+        let parse = |headers:&HashMap<String,String>,key:&str|->Vec<Mailbox>{
+            match headers.get(key){
+                Some(v)=>{parse_address_list(v)},
+                None=>{Vec::new()}
+            }
+        };
+        self.from = parse(&self.headers,"from");
+        self.sender = parse(&self.headers,"sender").into_iter().next();
+        self.reply_to = parse(&self.headers,"reply-to");
+        self.to = parse(&self.headers,"to");
+        self.cc = parse(&self.headers,"cc");
+        self.bcc = parse(&self.headers,"bcc");
+    }
+    ///parses the Date header, an unparsable date is left as None.
+    pub fn parse_date(&mut self){
+        match self.headers.get("date"){
+            Some(v)=>{self.date = EmailDate::parse(v).ok();},
+            None=>{self.date = None;}
+        }
+    }
+    ///parses every Received header into the hop chain, oldest hop first.
+    pub fn parse_received(&mut self){
+        self.received = hop_chain(self.header_all("received"));
+    }
+    ///seconds between the Date header and the dkim signature timestamp (t=), positive when signed after the date.
+    pub fn dkim_skew(&self)->Option<i64>{
+        let date = self.date?;
+        let signed = self.dkim.timestamp()?;
+        Some(date.seconds_until(signed))
+    }
     pub fn dkim(&mut self,value:Dkim){
         self.dkim_found = true;
         self.dkim = value;
     }
+    #[allow(clippy::needless_return)]
     pub fn parts(&mut self,handler:PartHandler)->Result<(),&'static str>{
+        return self.parts_with(handler,false);
+    }
+    ///decodes the parts, in lenient mode parts that fail are kept undecoded with their error instead of failing.
+    #[allow(clippy::needless_return)]
+    pub fn parts_with(&mut self,handler:PartHandler,lenient:bool)->Result<(),&'static str>{
         let mut handler = handler;
         handler.flush();
         self.parts = handler.finished;
-        match PartParser(self){
-            Ok(_)=>{
+        match PartParser(self,lenient){
+            Ok(refs)=>{
+                self.tree.link(&refs);
                 return Ok(());
             },
             Err(_e)=>{
@@ -218,15 +579,347 @@ impl EmailBody{
             }
         }
     }
+    ///the decoded part a tree node points at.
+    pub fn part(&self,node:&MimeNode)->Option<&Part>{
+        match node.part{
+            Some(PartRef::Body(i))=>{self.body.get(i)},
+            Some(PartRef::Attachment(i))=>{self.attachments.get(i)},
+            None=>{None}
+        }
+    }
+    ///the parts of every multipart/alternative group, least to most preferred as sent.
+    pub fn alternatives(&self)->Vec<Vec<&Part>>{
+        let mut collect = vec![];
+        for node in self.tree.find_all("multipart/alternative"){
+            let mut group = vec![];
+            for child in node.children.iter(){
+                //a nested multipart/related alternative is represented by its first leaf
+                if let Some(leaf) = child.leaves().first(){
+                    if let Some(p) = self.part(leaf){group.push(p);}
+                }
+            }
+            collect.push(group);
+        }
+        collect
+    }
+    ///the part with the given Content-ID, accepts a bare id, "<id>" or a "cid:" url (rfc 2392).
+    pub fn find_by_cid(&self,cid:&str)->Option<&Part>{
+        self.find_by_id(&normalize_cid(cid))
+    }
+    //a part by its normalized Content-ID, an exact match is preferred
+    fn find_by_id(&self,cid:&str)->Option<&Part>{
+        let parts = ||self.body.iter().chain(self.attachments.iter());
+        if let Some(v) = parts().find(|p|p.content_id().as_deref() == Some(cid)){return Some(v);}
+        parts().find(|p|p.content_id().map(|id|id.eq_ignore_ascii_case(cid)).unwrap_or(false))
+    }
+    ///the inline resources of every multipart/related group.
+    pub fn related(&self)->Vec<Related<'_>>{
+        let mut collect = vec![];
+        for node in self.tree.find_all("multipart/related"){
+            //the start parameter names the root by Content-ID, otherwise it is the first part
+            let start = node.content_type.1.get("start").map(|v|normalize_cid(v));
+            let mut root:Option<&Part> = None;
+            let mut resources:HashMap<String,&Part> = HashMap::new();
+            for (index,child) in node.children.iter().enumerate(){
+                let leaf = match child.leaves().first(){
+                    Some(v)=>{*v},
+                    None=>{continue;}
+                };
+                let part = match self.part(leaf){
+                    Some(v)=>{v},
+                    None=>{continue;}
+                };
+                let is_root = match &start{
+                    Some(id)=>{part.content_id().as_ref() == Some(id)},
+                    None=>{index == 0}
+                };
+                if is_root && root.is_none(){
+                    root = Some(part);
+                    continue;
+                }
+                if let Some(id) = part.content_id(){resources.insert(id,part);}
+                if let Some(location) = part.content_location(){resources.insert(location,part);}
+            }
+            collect.push(Related{root,resources});
+        }
+        collect
+    }
+    ///data: uris of every part with a Content-ID, keyed by the id, usable as `SanitizeOptions::cid_urls`.
+    pub fn cid_data_uris(&self)->HashMap<String,String>{
+        let mut collect = HashMap::new();
+        for part in self.body.iter().chain(self.attachments.iter()){
+            if let (Some(id),Some(uri)) = (part.content_id(),part.data_uri()){collect.insert(id,uri);}
+        }
+        collect
+    }
+    ///the html body with its `cid:` references replaced by data: uris of the referenced parts, see `sanitize::rewrite_cids`.
+    pub fn inline_html(&self)->Option<String>{
+        let html = self.body.iter().find_map(|p|match &p.decoded{
+            ContentDecoded::Html(v)=>{Some(v)},
+            _=>{None}
+        })?;
+        Some(rewrite_cids(html,|id|self.find_by_id(id).and_then(|p|p.data_uri())))
+    }
+    ///readable text of the email, the text/plain body is preferred and html only bodies are converted to text.
+    pub fn text_body(&self)->Option<String>{
+        for part in self.body.iter(){
+            if let ContentDecoded::String(_) = &part.decoded{
+                let content_type = part.content_type.0.as_str();
+                if content_type.is_empty() || content_type == "text/plain"{
+                    return part.unflowed();
+                }
+            }
+        }
+        for part in self.body.iter(){
+            if let ContentDecoded::Html(v) = &part.decoded{return Some(html::to_text(v));}
+        }
+        None
+    }
+    ///the main text/plain (or text/enriched) part of the email.
+    pub fn preferred_text(&self)->Option<&Part>{
+        self.preferred(&["text/plain","text/enriched"])
+    }
+    ///the main text/html part of the email.
+    pub fn preferred_html(&self)->Option<&Part>{
+        self.preferred(&["text/html"])
+    }
+    ///the body part best matching the content types in the order, most wanted first, "text/*" matches any text.
+    ///
+    ///of a multipart/alternative the best ranked alternative wins and on a tie the later one as it is the
+    ///richer, of a multipart/related only the root is considered and in any other multipart the first child with
+    ///a match is used, attachments and types that are not in the order like inline images or text/calendar are
+    ///skipped.
+    ///
+    /// ```
+    /// use letterman_email_body_parser::{Config,parse_bytes};
+    ///
+    /// let raw = "Content-Type: multipart/alternative; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nhi\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>hi</p>\r\n--b--\r\n";
+    /// let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+    /// assert_eq!(email.preferred(&["text/html","text/plain"]).unwrap().content_type.0,"text/html");
+    /// assert_eq!(email.preferred_text().unwrap().content_type.0,"text/plain");
+    /// ```
+    pub fn preferred(&self,order:&[&str])->Option<&Part>{
+        self.choose(&self.tree,order).map(|(_,part)|part)
+    }
+    //the best part below a node and its rank in the order
+    fn choose<'a>(&'a self,node:&MimeNode,order:&[&str])->Option<(usize,&'a Part)>{
+        if node.is_leaf(){
+            let part = match node.part{
+                Some(PartRef::Body(i))=>{self.body.get(i)?},
+                _=>{return None;}
+            };
+            if part.disposition.kind == DispositionKind::Attachment{
+                return None;
+            }
+            let content_type = if part.content_type.0.is_empty(){"text/plain"} else {part.content_type.0.as_str()};
+            let rank = order.iter().position(|o|{
+                match o.strip_suffix("/*"){
+                    Some(prefix)=>{content_type.split('/').next() == Some(prefix)},
+                    None=>{o.eq_ignore_ascii_case(content_type)}
+                }
+            })?;
+            return Some((rank,part));
+        }
+        let content_type = node.content_type.0.as_str();
+        if content_type == "multipart/alternative"{
+            let mut best:Option<(usize,&Part)> = None;
+            for child in node.children.iter(){
+                match (self.choose(child,order),best){
+                    (Some(found),Some(current)) if found.0 <= current.0=>{best = Some(found);},
+                    (Some(found),None)=>{best = Some(found);},
+                    _=>{}
+                }
+            }
+            return best;
+        }
+        if content_type == "multipart/related"{
+            let root = match node.content_type.1.get("start").map(|v|normalize_cid(v)){
+                Some(id)=>{
+                    node.children.iter().find(|c|{
+                        c.leaves().first().and_then(|l|self.part(l)).and_then(|p|p.content_id()).as_ref() == Some(&id)
+                    })
+                },
+                None=>{None}
+            };
+            return self.choose(root.or_else(||node.children.first())?,order);
+        }
+        node.children.iter().find_map(|c|self.choose(c,order))
+    }
+    ///filename, types, decoded size and digests of every attachment that has a body.
+    pub fn attachment_summary(&self)->Vec<AttachmentSummary>{
+        let mut collect = vec![];
+        for part in self.attachments.iter(){
+            let digest = match part.digest(){
+                Some(v)=>{v},
+                None=>{continue;}
+            };
+            collect.push(AttachmentSummary{
+                filename:part.filename().cloned(),
+                content_type:part.content_type.0.clone(),
+                content_id:part.content_id(),
+                sniffed_type:part.sniffed_type,
+                type_mismatch:part.type_mismatch,
+                size:digest.size,
+                sha256:digest.sha256,
+                md5:digest.md5
+            });
+        }
+        collect
+    }
+    ///emails nested in message/rfc822 parts.
+    pub fn messages(&self)->Vec<&EmailBody>{
+        let mut collect = vec![];
+        for part in self.body.iter().chain(self.attachments.iter()){
+            if let Some(v) = &part.message{collect.push(v.as_ref());}
+        }
+        collect
+    }
     pub async fn validate(&mut self,config:&Config)->Result<(),&'static str>{
         match DkimInit(self,config).await{
-            Ok(_)=>{
-                return Ok(());
-            },
+            Ok(_)=>{},
             Err(_e)=>{
                 println!("failed-dkim-validate : {:?}",_e);
                 return Err("failed-dkim-validate");
             }
         }
+        if !config.validate_nested{
+            return Ok(());
+        }
+        //headers only messages have nothing to validate
+        for part in self.body.iter_mut().chain(self.attachments.iter_mut()){
+            if part.content_type.0.ends_with("-headers"){
+                continue;
+            }
+            if let Some(nested) = &mut part.message{
+                match Box::pin(nested.validate(config)).await{
+                    Ok(_)=>{},
+                    Err(_e)=>{
+                        return Err("failed-dkim-validate-nested");
+                    }
+                }
+            }
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+//cid urls are percent encoded and may carry the angle brackets of the header
+pub(crate) fn normalize_cid(cid:&str)->String{
+    let cid = cid.trim();
+    let cid = if cid.get(..4).map_or(false,|p|p.eq_ignore_ascii_case("cid:")){&cid[4..]} else {cid};
+    let mut bytes:Vec<u8> = vec![];
+    let raw = cid.as_bytes();
+    let mut index = 0;
+    while index < raw.len(){
+        if raw[index] == b'%' && index + 2 < raw.len() && raw[index+1..index+3].iter().all(|b|b.is_ascii_hexdigit()){
+            if let Ok(v) = u8::from_str_radix(&String::from_utf8_lossy(&raw[index+1..index+3]),16){
+                bytes.push(v);
+                index += 3;
+                continue;
+            }
+        }
+        bytes.push(raw[index]);
+        index += 1;
+    }
+    let cid = String::from_utf8_lossy(&bytes).to_string();
+    cid.trim_start_matches('<').trim_end_matches('>').to_string()
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{normalize_cid,Config,ContentDisposition,DispositionKind,LineEnding,ContentDecoded};
+    use crate::parse_bytes;
+    use crate::sanitize::{sanitize,SanitizeOptions};
+
+    const RELATED:&str = "Content-Type: multipart/related; boundary=b; start=\"<ab\u{e9}>\"\r\n\r\n--b\r\nContent-Type: image/png\r\nContent-ID: <logo\u{e9}@x>\r\nContent-Transfer-Encoding: base64\r\n\r\naGk=\r\n--b\r\nContent-Type: text/html\r\nContent-ID: <ab\u{e9}>\r\n\r\n<img src=\"cid:logo%C3%A9@x\">\r\n--b--\r\n";
+
+    #[test]
+    fn disposition(){
+        let disposition = ContentDisposition::parse("Attachment; filename*=utf-8''r%C3%A9sum%C3%A9.pdf; size=\"1024\"; creation-date=\"Mon, 1 Jan 2001 10:00:00 +0000\"");
+        assert_eq!(disposition.kind,DispositionKind::Attachment);
+        assert_eq!(disposition.filename.as_deref(),Some("r\u{e9}sum\u{e9}.pdf"));
+        assert_eq!(disposition.size,Some(1024));
+        assert_eq!(disposition.creation_date.as_deref(),Some("Mon, 1 Jan 2001 10:00:00 +0000"));
+        assert_eq!(ContentDisposition::parse("form-data; name=x").kind,DispositionKind::Other("form-data".to_string()));
+        assert_eq!(ContentDisposition::parse("").kind,DispositionKind::None);
+    }
+
+    #[test]
+    fn line_endings(){
+        let config = Config::new().unwrap();
+        let lf = parse_bytes(b"Subject: hi\nContent-Type: multipart/mixed; boundary=b\n\n--b\nContent-Type: text/plain\n\none\ntwo\n--b--\n",&config).unwrap();
+        assert_eq!(lf.line_ending,LineEnding::Lf);
+        assert_eq!(lf.headers.get("subject").map(|v|v.as_str()),Some("hi"));
+        assert!(matches!(&lf.body[0].decoded,ContentDecoded::String(v) if v == "one\ntwo"));
+        let mixed = parse_bytes(b"Subject: hi\r\nFrom: a@x.org\n\nbody\r\n",&config).unwrap();
+        assert_eq!(mixed.line_ending,LineEnding::Mixed);
+        assert_eq!(mixed.from[0].domain,"x.org");
+        assert_eq!(LineEnding::Mixed.to_crlf(b"a\nb\r\nc").as_ref(),b"a\r\nb\r\nc");
+        assert_eq!(LineEnding::from_counts(3,0),LineEnding::Crlf);
+    }
+
+    #[test]
+    fn cid_forms(){
+        assert_eq!(normalize_cid("cid:a%40b"),"a@b");
+        assert_eq!(normalize_cid(" CID:<a@b> "),"a@b");
+        assert_eq!(normalize_cid("<ab\u{e9}>"),"ab\u{e9}");
+        assert_eq!(normalize_cid("ab\u{e9}"),"ab\u{e9}");
+        assert_eq!(normalize_cid("\u{e9}\u{e9}"),"\u{e9}\u{e9}");
+        assert_eq!(normalize_cid("cid:logo%C3%A9"),"logo\u{e9}");
+        assert_eq!(normalize_cid("a%+4b%4"),"a%+4b%4");
+    }
+
+    #[test]
+    fn non_ascii_ids(){
+        let config = Config::new().unwrap();
+        let email = parse_bytes(RELATED.as_bytes(),&config).unwrap();
+        assert_eq!(email.find_by_cid("cid:logo%C3%A9@x").unwrap().content_type.0,"image/png");
+        assert_eq!(email.find_by_cid("<LOGO\u{e9}@X>").unwrap().content_type.0,"image/png");
+        let related = email.related();
+        assert_eq!(related.len(),1);
+        assert_eq!(related[0].root.unwrap().content_type.0,"text/html");
+        assert!(related[0].resources.contains_key("logo\u{e9}@x"));
+        assert_eq!(email.preferred_html().unwrap().content_type.0,"text/html");
+        assert_eq!(email.inline_html().unwrap(),"<img src=\"data:image/png;base64,aGk=\">");
+    }
+
+    #[test]
+    fn inline_html_urls_only(){
+        let html = "<!DOCTYPE html><html><style>b{background:url( 'cid:a@x' )}</style><body background=cid:a@x><p title=\"cid:a@x\" style=\"background:URL(cid:a@x)\">see cid:a@x</p><a href=\"CID:%61@x\">x</a><img src=\"cid:missing\"><br/></body></html>";
+        let raw = format!("Content-Type: multipart/related; boundary=b\r\n\r\n--b\r\nContent-Type: text/html\r\n\r\n{}\r\n--b\r\nContent-Type: image/png\r\nContent-ID: <a@x>\r\nContent-Transfer-Encoding: base64\r\n\r\naGk=\r\n--b--\r\n",html);
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        let uri = "data:image/png;base64,aGk=";
+        assert_eq!(email.inline_html().unwrap(),format!(
+            "<!DOCTYPE html><html><style>b{{background:url( '{0}' )}}</style><body background=\"{0}\"><p title=\"cid:a@x\" style=\"background:URL({0})\">see cid:a@x</p><a href=\"{0}\">x</a><img src=\"cid:missing\"><br /></body></html>",
+            uri
+        ));
+    }
+
+    #[test]
+    fn sanitize_non_ascii_cid(){
+        let (html,report) = sanitize("<img src=\"cid:abc\u{e9}\"><img src=\"cid:\u{e9}\">",&SanitizeOptions::new());
+        assert_eq!(html,"<img><img>");
+        assert_eq!(report.blocked_urls.len(),2);
+    }
+
+    #[test]
+    fn preferred_body(){
+        let raw = "Content-Type: multipart/mixed; boundary=m\r\n\r\n--m\r\nContent-Type: text/plain\r\nContent-Disposition: attachment; filename=notes.txt\r\n\r\nnotes\r\n--m\r\nContent-Type: multipart/alternative; boundary=a\r\n\r\n--a\r\nContent-Type: text/plain\r\n\r\nplain\r\n--a\r\nContent-Type: text/enriched\r\n\r\n<bold>rich</bold>\r\n--a\r\nContent-Type: multipart/related; boundary=r\r\n\r\n--r\r\nContent-Type: text/html\r\n\r\n<p>html</p>\r\n--r\r\nContent-Type: image/png\r\nContent-ID: <i@x>\r\nContent-Transfer-Encoding: base64\r\n\r\naGk=\r\n--r--\r\n--a\r\nContent-Type: text/calendar; method=REQUEST\r\n\r\nBEGIN:VCALENDAR\r\n--a--\r\n--m--\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        let kind = |part:Option<&super::Part>|part.map(|p|p.content_type.0.clone());
+        //the attached text file comes first but is never the body
+        assert_eq!(kind(email.preferred_text()).as_deref(),Some("text/plain"));
+        assert!(matches!(&email.preferred_text().unwrap().decoded,ContentDecoded::String(v) if v == "plain"));
+        assert_eq!(kind(email.preferred_html()).as_deref(),Some("text/html"));
+        //the best ranked alternative wins wherever it is
+        assert_eq!(kind(email.preferred(&["text/enriched","text/plain"])).as_deref(),Some("text/enriched"));
+        assert_eq!(kind(email.preferred(&["text/plain","text/enriched"])).as_deref(),Some("text/plain"));
+        //on a tie the later alternative is the richer
+        assert_eq!(kind(email.preferred(&["text/*"])).as_deref(),Some("text/calendar"));
+        assert_eq!(kind(email.preferred(&["text/calendar","text/html"])).as_deref(),Some("text/calendar"));
+        //only the root of a related part is a body
+        assert_eq!(kind(email.preferred(&["image/png"])),None);
+    }
+
+}
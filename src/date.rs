@@ -0,0 +1,268 @@
+use crate::parser::strip_comments;
+use std::time::{SystemTime,UNIX_EPOCH};
+
+///a parsed rfc 5322 date, timestamp is in unix seconds (utc) and offset is the zone offset in seconds east of utc.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub struct EmailDate{
+    pub timestamp:i64,
+    pub offset:i32
+}
+
+const MONTHS:[&str;12] = ["jan","feb","mar","apr","may","jun","jul","aug","sep","oct","nov","dec"];
+const DAYS:[&str;7] = ["mon","tue","wed","thu","fri","sat","sun"];
+
+impl EmailDate{
+    ///parses a date-time as found in the Date header.
+    ///
+    ///obsolete syntax is accepted, two digit years (< 50 is 20xx), three digit years, named zones like
+    ///PST or GMT, military zones, missing seconds, missing day of week and trailing comments, unknown zones
+    ///are read as -0000 (offset 0).
+    pub fn parse(input:&str)->Result<EmailDate,&'static str>{
+
+        let clean = strip_comments(input).replace(','," ");
+        let tokens:Vec<&str> = clean.split_whitespace().collect();
+
+        let mut day:Option<i64> = None;
+        let mut month:Option<i64> = None;
+        let mut year:Option<i64> = None;
+        let mut time:Option<(i64,i64,i64)> = None;
+        let mut offset:Option<i32> = None;
+
+        for token in tokens{
+            let lower = token.to_lowercase();
+            if lower.contains(':') && time.is_none(){
+                match parse_time(&lower){
+                    Some(v)=>{time = Some(v);},
+                    None=>{return Err("invalid-date-time");}
+                }
+            } else if (lower.starts_with('+') || lower.starts_with('-')) && time.is_some(){
+                match parse_numeric_zone(&lower){
+                    Some(v)=>{offset = Some(v);},
+                    None=>{return Err("invalid-date-zone");}
+                }
+            } else if lower.chars().all(|c|c.is_ascii_digit()){
+                //days and years never need more than 4 digits, longer numbers would overflow the day count
+                if lower.len() > 4{
+                    return Err("invalid-date-number");
+                }
+                let value:i64 = match lower.parse::<i64>(){
+                    Ok(v)=>{v},
+                    Err(_)=>{return Err("invalid-date-number");}
+                };
+                if day.is_none() && lower.len() <= 2 && year.is_none(){
+                    day = Some(value);
+                } else if year.is_none(){
+                    year = Some(match lower.len(){
+                        1|2=>{if value < 50 {value + 2000} else {value + 1900}},
+                        3=>{value + 1900},
+                        _=>{value}
+                    });
+                } else {
+                    return Err("invalid-date-extra_number");
+                }
+            } else if lower.len() >= 2 && lower.chars().all(|c|c.is_ascii_alphabetic()){
+                let prefix = &lower[..lower.len().min(3)];
+                if month.is_none() && MONTHS.contains(&prefix) && time.is_none(){
+                    month = Some(MONTHS.iter().position(|m|*m == prefix).unwrap_or(0) as i64 + 1);
+                } else if DAYS.contains(&prefix) && time.is_none(){
+                    continue;
+                } else if time.is_some() && offset.is_none(){
+                    //rfc 5322 says to treat unknown zones like CET or JST as -0000
+                    offset = Some(named_zone(&lower).unwrap_or(0));
+                } else if time.is_some(){
+                    //a zone name repeated after the numeric offset
+                    continue;
+                } else {
+                    return Err("invalid-date-token");
+                }
+            } else if lower.len() == 1 && time.is_some() && offset.is_none(){
+                //military zones are too ambiguous, rfc 5322 says to treat them as -0000
+                offset = Some(0);
+            } else {
+                return Err("invalid-date-token");
+            }
+        }
+
+        let day = day.ok_or("not_found-date-day")?;
+        let month = month.ok_or("not_found-date-month")?;
+        let year = year.ok_or("not_found-date-year")?;
+        let (hour,minute,second) = time.ok_or("not_found-date-time")?;
+        let offset = offset.unwrap_or(0);
+
+        if !(1900..=9999).contains(&year){
+            return Err("invalid-date-year");
+        }
+        if day < 1 || day > days_in_month(year,month){
+            return Err("invalid-date-day");
+        }
+
+        let days = days_from_civil(year,month,day);
+        let local = days * 86400 + hour * 3600 + minute * 60 + second;
+
+        Ok(EmailDate{
+            timestamp:local - offset as i64,
+            offset
+        })
+
+    }
+    ///true when the date is further in the future than the allowed clock skew in seconds.
+    pub fn is_future(&self,skew:i64)->bool{
+        self.timestamp > now() + skew
+    }
+    ///seconds between this date and a unix timestamp, like the dkim t= tag, positive when the timestamp is later.
+    pub fn seconds_until(&self,timestamp:i64)->i64{
+        timestamp - self.timestamp
+    }
+    ///utc date and time as (year,month,day,hour,minute,second).
+    pub fn utc(&self)->(i64,i64,i64,i64,i64,i64){
+        let days = self.timestamp.div_euclid(86400);
+        let rest = self.timestamp.rem_euclid(86400);
+        let (year,month,day) = civil_from_days(days);
+        (year,month,day,rest / 3600,(rest % 3600) / 60,rest % 60)
+    }
+}
+
+fn now()->i64{
+    match SystemTime::now().duration_since(UNIX_EPOCH){
+        Ok(v)=>{v.as_secs() as i64},
+        Err(_)=>{0}
+    }
+}
+
+fn parse_time(input:&str)->Option<(i64,i64,i64)>{
+    let hold:Vec<&str> = input.split(':').collect();
+    if hold.len() < 2 || hold.len() > 3{
+        return None;
+    }
+    let hour = hold[0].parse::<i64>().ok()?;
+    let minute = hold[1].parse::<i64>().ok()?;
+    let mut second = 0;
+    if hold.len() == 3{
+        second = hold[2].parse::<i64>().ok()?;
+    }
+    //a leap second is folded into the next minute
+    if hour > 23 || minute > 59 || second > 60{
+        return None;
+    }
+    Some((hour,minute,second))
+}
+
+fn parse_numeric_zone(input:&str)->Option<i32>{
+    let digits = &input[1..];
+    if digits.len() != 4 || !digits.chars().all(|c|c.is_ascii_digit()){
+        return None;
+    }
+    let hours = digits[..2].parse::<i32>().ok()?;
+    let minutes = digits[2..].parse::<i32>().ok()?;
+    if minutes > 59{
+        return None;
+    }
+    let offset = hours * 3600 + minutes * 60;
+    if input.starts_with('-'){
+        return Some(-offset);
+    }
+    Some(offset)
+}
+
+fn named_zone(input:&str)->Option<i32>{
+    let hours = match input{
+        "ut"|"utc"|"gmt"|"z"=>0,
+        "edt"=>-4,
+        "est"|"cdt"=>-5,
+        "cst"|"mdt"=>-6,
+        "mst"|"pdt"=>-7,
+        "pst"=>-8,
+        _=>{return None;}
+    };
+    Some(hours * 3600)
+}
+
+fn is_leap_year(year:i64)->bool{
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year:i64,month:i64)->i64{
+    match month{
+        1|3|5|7|8|10|12=>31,
+        4|6|9|11=>30,
+        _=>{if is_leap_year(year) {29} else {28}}
+    }
+}
+
+//days since 1970-01-01 for a proleptic gregorian date
+fn days_from_civil(year:i64,month:i64,day:i64)->i64{
+    let year = if month <= 2 {year - 1} else {year};
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days:i64)->(i64,i64,i64){
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+    (year,month,day)
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::EmailDate;
+
+    fn utc(input:&str)->(i64,i64,i64,i64,i64,i64){
+        EmailDate::parse(input).unwrap().utc()
+    }
+
+    #[test]
+    fn current_syntax(){
+        let date = EmailDate::parse("Wed, 23 Feb 2022 12:53:58 +0530").unwrap();
+        assert_eq!(date.offset,19800);
+        assert_eq!(date.utc(),(2022,2,23,7,23,58));
+    }
+
+    #[test]
+    fn obsolete_years(){
+        assert_eq!(utc("1 Jan 01 00:00 +0000").0,2001);
+        assert_eq!(utc("1 Jan 99 00:00 +0000").0,1999);
+        assert_eq!(utc("1 Jan 101 00:00 +0000").0,2001);
+    }
+
+    #[test]
+    fn obsolete_zones(){
+        assert_eq!(EmailDate::parse("Mon, 1 Jan 2001 10:00:00 UT").unwrap().offset,0);
+        assert_eq!(EmailDate::parse("Mon, 1 Jan 2001 10:00:00 GMT").unwrap().offset,0);
+        assert_eq!(EmailDate::parse("Mon, 1 Jan 2001 10:00:00 PST").unwrap().offset,-8 * 3600);
+        assert_eq!(EmailDate::parse("Mon, 1 Jan 2001 10:00:00 EDT").unwrap().offset,-4 * 3600);
+        assert_eq!(EmailDate::parse("Mon, 1 Jan 2001 10:00:00 Q").unwrap().offset,0);
+        assert_eq!(utc("Mon, 1 Jan 2001 10:00:00 CEST"),(2001,1,1,10,0,0));
+        assert_eq!(EmailDate::parse("Mon, 1 Jan 2001 10:00:00 JST").unwrap().offset,0);
+        assert_eq!(EmailDate::parse("Mon, 1 Jan 2001 10:00:00 XX").unwrap().offset,0);
+        assert_eq!(utc("Mon, 1 Jan 2001 10:00:00 +0100 CET"),(2001,1,1,9,0,0));
+    }
+
+    #[test]
+    fn obsolete_layout(){
+        assert_eq!(utc("1 Jan 2001 10:00 +0000"),(2001,1,1,10,0,0));
+        assert_eq!(utc("Mon , 1 Jan 2001 10:00:00 -0100 (Central Europe)"),(2001,1,1,11,0,0));
+        assert_eq!(utc("Mon, 1 (day) Jan 2001 10:00:00 +0000"),(2001,1,1,10,0,0));
+    }
+
+    #[test]
+    fn invalid(){
+        assert_eq!(EmailDate::parse("1 Jan 999999999999999999 00:00 +0000"),Err("invalid-date-number"));
+        assert_eq!(EmailDate::parse("1 Jan 0999 00:00 +0000"),Err("invalid-date-year"));
+        assert_eq!(EmailDate::parse("31 Feb 2001 00:00 +0000"),Err("invalid-date-day"));
+        assert_eq!(EmailDate::parse("1 Jan 2001 25:00 +0000"),Err("invalid-date-time"));
+        assert!(EmailDate::parse("1 Jan 2001").is_err());
+    }
+
+}
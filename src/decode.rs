@@ -0,0 +1,178 @@
+use crate::config::ContentEncoding;
+use base64::decode as Base64Decode;
+use quoted_printable::decode as QPDecode;
+use quoted_printable::ParseMode as QpParseMode;
+
+///decodes a transfer encoded body piece by piece, only an incomplete base64 quantum or qp line is held back.
+///
+///in lenient mode data that fails strict decoding is decoded like `parse_bytes` does for whole parts, base64
+///with `base64_lenient` and quoted-printable in robust mode, and `error` tells why.
+#[derive(Debug,Clone)]
+pub struct StreamDecoder{
+    encoding:ContentEncoding,
+    carry:Vec<u8>,
+    lenient:bool,
+    ///why strict decoding failed, only set in lenient mode.
+    pub error:Option<&'static str>
+}
+
+impl StreamDecoder{
+    pub fn new(encoding:&ContentEncoding,lenient:bool)->StreamDecoder{
+        StreamDecoder{
+            encoding:encoding.clone(),
+            carry:Vec::new(),
+            lenient,
+            error:None
+        }
+    }
+    ///decodes as much of the data as possible.
+    pub fn push(&mut self,data:&[u8])->Result<Vec<u8>,&'static str>{
+        match self.encoding{
+            ContentEncoding::Base64=>{
+                if self.error.is_some(){
+                    return Ok(self.base64_run(data,false));
+                }
+                self.carry.extend(data.iter().filter(|b|!b.is_ascii_whitespace()));
+                self.base64(false)
+            },
+            ContentEncoding::Qp=>{
+                self.carry.extend_from_slice(data);
+                //soft line breaks and escapes never span a line ending
+                let end = match self.carry.iter().rposition(|b|*b == b'\n'){
+                    Some(v)=>{v + 1},
+                    None=>{return Ok(Vec::new());}
+                };
+                //the decoder treats a line ending at the end of its input as the end of the body, a final
+                //line is added so it decodes like the line breaks inside the body
+                let mut lines:Vec<u8> = self.carry.drain(..end).collect();
+                lines.push(b'x');
+                let mut decoded = self.qp(&lines)?;
+                decoded.pop();
+                Ok(decoded)
+            },
+            _=>{
+                Ok(data.to_vec())
+            }
+        }
+    }
+    ///decodes whatever was held back.
+    pub fn finish(&mut self)->Result<Vec<u8>,&'static str>{
+        match self.encoding{
+            ContentEncoding::Base64=>{
+                if self.error.is_some(){
+                    return Ok(self.base64_run(b"",true));
+                }
+                self.base64(true)
+            },
+            ContentEncoding::Qp=>{
+                let rest = std::mem::take(&mut self.carry);
+                self.qp(&rest)
+            },
+            _=>{
+                Ok(Vec::new())
+            }
+        }
+    }
+    //complete 4 byte quanta, a padded quantum ends a base64 run so it is decoded on its own
+    fn base64(&mut self,all:bool)->Result<Vec<u8>,&'static str>{
+        let mut collect:Vec<u8> = Vec::new();
+        loop{
+            let mut end = self.carry.len() - self.carry.len() % 4;
+            if all{
+                end = self.carry.len();
+            }
+            if let Some(v) = self.carry[..end].chunks(4).position(|q|q.contains(&b'=')){end = (v + 1) * 4;}
+            end = end.min(self.carry.len());
+            if end == 0{
+                return Ok(collect);
+            }
+            match Base64Decode(&self.carry[..end]){
+                Ok(v)=>{
+                    collect.extend_from_slice(&v);
+                    self.carry.drain(..end);
+                },
+                Err(_)=>{
+                    if !self.lenient{
+                        return Err("failed-decode-base64");
+                    }
+                    //the quanta decoded so far are valid, the rest is decoded leniently
+                    self.error = Some("failed-decode-base64");
+                    let rest = std::mem::take(&mut self.carry);
+                    collect.extend(self.base64_run(&rest,all));
+                    return Ok(collect);
+                }
+            }
+        }
+    }
+    //lenient base64, the carry holds the alphabet characters of the current run
+    fn base64_run(&mut self,data:&[u8],all:bool)->Vec<u8>{
+        let mut collect:Vec<u8> = Vec::new();
+        for b in data.iter(){
+            if *b == b'='{
+                collect.extend(lenient_run(std::mem::take(&mut self.carry)));
+            } else if b.is_ascii_alphanumeric() || *b == b'+' || *b == b'/'{
+                self.carry.push(*b);
+            }
+        }
+        let end = if all{self.carry.len()} else {self.carry.len() - self.carry.len() % 4};
+        let run:Vec<u8> = self.carry.drain(..end).collect();
+        collect.extend(lenient_run(run));
+        collect
+    }
+    fn qp(&mut self,lines:&[u8])->Result<Vec<u8>,&'static str>{
+        if let Ok(v) = QPDecode(lines,QpParseMode::Strict){
+            return Ok(v);
+        }
+        if !self.lenient{
+            return Err("failed-decode-qp");
+        }
+        self.error = Some("failed-decode-qp");
+        match QPDecode(lines,QpParseMode::Robust){
+            Ok(v)=>{Ok(v)},
+            Err(_)=>{Err("failed-decode-qp")}
+        }
+    }
+}
+
+///decodes base64 that failed strict decoding, characters outside the alphabet are skipped and every run
+///ended by padding is decoded on its own, a dangling sextet is dropped.
+pub fn base64_lenient(data:&[u8])->Vec<u8>{
+    let mut collect:Vec<u8> = Vec::new();
+    for run in data.split(|b|*b == b'='){
+        let clean:Vec<u8> = run.iter().filter(|b|b.is_ascii_alphanumeric() || **b == b'+' || **b == b'/').copied().collect();
+        collect.extend(lenient_run(clean));
+    }
+    collect
+}
+
+//decodes the alphabet characters of one run, padding is added back
+fn lenient_run(mut clean:Vec<u8>)->Vec<u8>{
+    if clean.len() % 4 == 1{
+        clean.pop();
+    }
+    let padding = (4 - clean.len() % 4) % 4;
+    clean.extend_from_slice(&b"=="[..padding]);
+    base64::decode_config(&clean,base64::STANDARD.decode_allow_trailing_bits(true)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::StreamDecoder;
+    use crate::config::ContentEncoding;
+    use quoted_printable::{decode,ParseMode};
+
+    #[test]
+    fn qp_line_breaks_across_chunks(){
+        let body = b"a=3D1\r\n\r\nsoft=\r\nbreak  \r\nend";
+        let whole = decode(&body[..],ParseMode::Strict).unwrap();
+        for split in 0..body.len(){
+            let mut decoder = StreamDecoder::new(&ContentEncoding::Qp,false);
+            let mut collect = decoder.push(&body[..split]).unwrap();
+            collect.extend(decoder.push(&body[split..]).unwrap());
+            collect.extend(decoder.finish().unwrap());
+            assert_eq!(collect,whole,"split at {}",split);
+        }
+    }
+
+}
@@ -0,0 +1,140 @@
+use openssl::hash::{Hasher,MessageDigest};
+use openssl::sha::Sha256;
+use serde::Serialize;
+
+///digests and size of a decoded body.
+#[derive(Debug,Clone,PartialEq,Serialize)]
+pub struct Digest{
+    ///lowercase hex sha-256.
+    pub sha256:String,
+    ///lowercase hex md5, for systems that still key on it.
+    pub md5:String,
+    pub size:u64
+}
+
+impl Digest{
+    ///digests of the whole body at once.
+    ///
+    /// ```
+    /// use letterman_email_body_parser::digest::Digest;
+    ///
+    /// let digest = Digest::of(b"hello").unwrap();
+    /// assert_eq!(digest.md5,"5d41402abc4b2a76b9719d911017c592");
+    /// assert_eq!(digest.size,5);
+    /// ```
+    pub fn of(bytes:&[u8])->Result<Digest,&'static str>{
+        let mut hasher = DigestHasher::new()?;
+        hasher.update(bytes)?;
+        hasher.finish()
+    }
+}
+
+///computes a `Digest` piece by piece while a body is streamed.
+pub struct DigestHasher{
+    sha256:Sha256,
+    md5:Hasher,
+    size:u64
+}
+
+impl DigestHasher{
+    pub fn new()->Result<DigestHasher,&'static str>{
+        let md5:Hasher = match Hasher::new(MessageDigest::md5()){
+            Ok(v)=>{v},
+            Err(_)=>{
+                return Err("failed-init-md5");
+            }
+        };
+        Ok(DigestHasher{
+            sha256:Sha256::new(),
+            md5,
+            size:0
+        })
+    }
+    pub fn update(&mut self,bytes:&[u8])->Result<(),&'static str>{
+        self.sha256.update(bytes);
+        match self.md5.update(bytes){
+            Ok(_)=>{},
+            Err(_)=>{
+                return Err("failed-update-md5");
+            }
+        }
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+    pub fn finish(mut self)->Result<Digest,&'static str>{
+        let md5 = match self.md5.finish(){
+            Ok(v)=>{v},
+            Err(_)=>{
+                return Err("failed-finish-md5");
+            }
+        };
+        Ok(Digest{
+            sha256:hex(&self.sha256.finish()),
+            md5:hex(&md5),
+            size:self.size
+        })
+    }
+}
+
+///what dlp and dedupe systems need to know about an attachment, see `EmailBody::attachment_summary`.
+#[derive(Debug,Clone,PartialEq,Serialize)]
+pub struct AttachmentSummary{
+    pub filename:Option<String>,
+    pub content_type:String,
+    pub content_id:Option<String>,
+    pub sniffed_type:Option<&'static str>,
+    pub type_mismatch:bool,
+    ///decoded size in bytes.
+    pub size:u64,
+    pub sha256:String,
+    pub md5:String
+}
+
+fn hex(bytes:&[u8])->String{
+    let mut collect = String::with_capacity(bytes.len() * 2);
+    for byte in bytes{
+        collect.push_str(&format!("{:02x}",byte));
+    }
+    collect
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::Digest;
+    use crate::{Config,parse_bytes,parse_reader_sink};
+
+    const CSV:&str = "Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nhi\r\n--b\r\nContent-Type: text/csv; charset=utf-8\r\nContent-Disposition: attachment; filename=a.csv\r\nContent-Transfer-Encoding: base64\r\n\r\nYSxiDQpjLGQNCg==\r\n--b--\r\n";
+    const CSV_SHA256:&str = "7b80bae4f56bdb4b6b952938c42715cde7b1610cb021e70148d164c80663c230";
+
+    #[test]
+    fn digest_of(){
+        let digest = Digest::of(b"a,b\r\nc,d\r\n").unwrap();
+        assert_eq!(digest.sha256,CSV_SHA256);
+        assert_eq!(digest.size,10);
+    }
+
+    #[test]
+    fn text_attachment_keeps_its_bytes(){
+        let email = parse_bytes(CSV.as_bytes(),&Config::new().unwrap()).unwrap();
+        let summary = email.attachment_summary();
+        assert_eq!(summary.len(),1);
+        assert_eq!(summary[0].filename.as_deref(),Some("a.csv"));
+        assert_eq!(summary[0].size,10);
+        assert_eq!(summary[0].sha256,CSV_SHA256);
+    }
+
+    #[tokio::test]
+    async fn sink_matches_memory(){
+        let config = Config::new().unwrap();
+        let email = parse_reader_sink(CSV.as_bytes(),&config,|part|{
+            if part.filename().is_some(){
+                return Some(tokio::io::sink());
+            }
+            None
+        }).await.unwrap();
+        let memory = parse_bytes(CSV.as_bytes(),&config).unwrap();
+        assert_eq!(email.attachment_summary(),memory.attachment_summary());
+    }
+
+}
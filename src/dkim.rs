@@ -6,6 +6,7 @@ use openssl::sign::Verifier;
 use base64::decode as Base64Decode;
 use openssl::hash::MessageDigest;
 
+#[allow(clippy::needless_late_init,clippy::single_match,clippy::needless_borrow,clippy::needless_return)]
 pub async fn init(email:&mut EmailBody,config:&Config)->Result<(),&'static str>{
 
     match check_basic_validation(email){
@@ -118,6 +119,7 @@ pub async fn init(email:&mut EmailBody,config:&Config)->Result<(),&'static str>{
 
 }
 
+#[allow(clippy::needless_late_init,clippy::needless_borrow,clippy::needless_return)]
 pub fn verify_dkim_signature(verification_string:String,key:&PKey<Public>,signature:String)->Result<bool,&'static str>{
 
     // println!("\n{}\n",verification_string);
@@ -167,6 +169,7 @@ pub fn verify_dkim_signature(verification_string:String,key:&PKey<Public>,signat
 
 }
 
+#[allow(clippy::needless_late_init,clippy::needless_return)]
 pub async fn get_dkim_sender_key(config:&Config,key_name:&String)->Result<PKey<Public>,&'static str>{
 
     let mut dkim_key_string = String::new();
@@ -233,6 +236,7 @@ pub async fn get_dkim_sender_key(config:&Config,key_name:&String)->Result<PKey<P
 
 }
 
+#[allow(clippy::needless_late_init,clippy::unnecessary_to_owned,clippy::len_zero,clippy::needless_return)]
 pub fn get_dkim_signature_string(email:&mut EmailBody)->Result<String,&'static str>{
 
     let parts:Vec<&str>;
@@ -293,6 +297,7 @@ pub fn get_dkim_signature_string(email:&mut EmailBody)->Result<String,&'static s
 
 }
 
+#[allow(clippy::needless_return)]
 pub fn check_basic_validation(email:&mut EmailBody)->Result<(),&'static str>{
 
     // println!("headers : {:?}",email.headers);
@@ -313,21 +318,18 @@ pub fn check_basic_validation(email:&mut EmailBody)->Result<(),&'static str>{
 
 }
 
-pub fn get_sender_from_email_headers(email:&mut EmailBody,config:&Config)->Result<String,&'static str>{
+#[allow(clippy::needless_return)]
+pub fn get_sender_from_email_headers(email:&mut EmailBody,_config:&Config)->Result<String,&'static str>{
 
-    let from:&str;
-    match email.headers.get("from"){
-        Some(v)=>{from = v;},
-        None=>{return Err("not_found-from-header");}
+    if !email.headers.contains_key("from"){
+        return Err("not_found-from-header");
     }
 
-    match config.from_regex.captures(from){
-        Some(captures)=>{
-            match captures.get(2){
-                Some(domain)=>{
-                    return Ok(domain.as_str().to_string());
-                },
-                None=>{
+    match email.from.first(){
+        Some(mailbox)=>{
+            match mailbox.ascii_domain(){
+                Ok(v)=>{return Ok(v);},
+                Err(_)=>{
                     return Err("failed-get_email-from_header");
                 }
             }
@@ -339,6 +341,7 @@ pub fn get_sender_from_email_headers(email:&mut EmailBody,config:&Config)->Resul
 
 }
 
+#[allow(clippy::needless_late_init,clippy::needless_return)]
 pub fn get_dkim_key_name(email:&mut EmailBody,sender:&String)->Result<String,&'static str>{
     let selector:&str;
     match email.dkim.features.get("s"){
@@ -0,0 +1,193 @@
+///a reflowed paragraph of format=flowed text (rfc 3676) and the quote depth it was written at.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Paragraph{
+    pub depth:usize,
+    pub text:String
+}
+
+const SIGNATURE:&str = "-- ";
+
+///joins the soft broken lines of format=flowed text into paragraphs.
+///
+///quote marks and space stuffing are removed, with delsp the space in front of a soft break is deleted too,
+///a flowed line followed by a line of another quote depth ends its paragraph.
+pub fn paragraphs(text:&str,delsp:bool)->Vec<Paragraph>{
+
+    let mut collect:Vec<Paragraph> = vec![];
+    let mut current:Option<Paragraph> = None;
+
+    for line in text.split('\n'){
+
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let depth = line.chars().take_while(|c|*c == '>').count();
+        let mut content = &line[depth..];
+        if content.starts_with(' '){
+            content = &content[1..];
+        }
+
+        if current.as_ref().map_or(false,|p|p.depth != depth){
+            collect.extend(current.take());
+        }
+
+        let flowed = content.ends_with(' ') && content != SIGNATURE;
+        if flowed && delsp{
+            content = &content[..content.len()-1];
+        }
+
+        match &mut current{
+            Some(p)=>{p.text.push_str(content);},
+            None=>{
+                current = Some(Paragraph{depth,text:content.to_string()});
+            }
+        }
+
+        if !flowed{
+            collect.extend(current.take());
+        }
+
+    }
+
+    collect.extend(current.take());
+    collect
+
+}
+
+///reflows format=flowed text, every paragraph becomes one line and quoted paragraphs keep their quote marks.
+///
+/// ```
+/// use letterman_email_body_parser::flowed;
+///
+/// let text = "this is a long \nline\n> quoted \n> reply\n";
+/// assert_eq!(flowed::decode(text,false),"this is a long line\n> quoted reply\n");
+/// ```
+pub fn decode(text:&str,delsp:bool)->String{
+    let mut collect:Vec<String> = vec![];
+    for paragraph in paragraphs(text,delsp){
+        if paragraph.depth > 0{
+            collect.push(format!("{} {}",">".repeat(paragraph.depth),paragraph.text));
+        } else {
+            collect.push(paragraph.text);
+        }
+    }
+    collect.join("\n")
+}
+
+///produces format=flowed (delsp=no) text with crlf line endings for composing.
+///
+///lines longer than the width are soft broken at spaces, lines starting with ">" keep their quote depth on every
+///line they are broken into, trailing spaces of hard breaks are removed and lines are space stuffed where needed.
+pub fn encode(text:&str,width:usize)->String{
+
+    let mut collect:Vec<String> = vec![];
+
+    for line in text.split('\n'){
+
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line == SIGNATURE{
+            collect.push(line.to_string());
+            continue;
+        }
+        let depth = line.chars().take_while(|c|*c == '>').count();
+        let mut content = &line[depth..];
+        let prefix = if depth > 0{
+            if content.starts_with(' '){
+                content = &content[1..];
+            }
+            format!("{} ",">".repeat(depth))
+        } else {
+            String::new()
+        };
+        let content = content.trim_end_matches(' ');
+
+        let room = width.saturating_sub(prefix.len()).max(1);
+        for piece in wrap(content,room){
+            let mut out = prefix.clone();
+            if depth == 0 && (piece.starts_with(' ') || piece.starts_with('>') || piece.starts_with("From ")){
+                out.push(' ');
+            }
+            out.push_str(piece);
+            collect.push(out);
+        }
+
+    }
+
+    collect.join("\r\n")
+
+}
+
+//splits a line at spaces into pieces no longer than the width where possible, every piece but the last keeps
+//the space it was broken at so the break is soft
+fn wrap(line:&str,width:usize)->Vec<&str>{
+    let mut collect:Vec<&str> = vec![];
+    let mut rest = line;
+    while rest.len() > width{
+        let window = &rest[..floor_boundary(rest,width + 1)];
+        let cut = match window.rfind(' '){
+            Some(v) if v > 0=>{v + 1},
+            _=>{
+                //a word longer than the width is broken after it
+                match rest.char_indices().skip(1).find(|(_,c)|*c == ' '){
+                    Some((v,_))=>{v + 1},
+                    None=>{break;}
+                }
+            }
+        };
+        if cut >= rest.len(){
+            break;
+        }
+        collect.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    collect.push(rest);
+    collect
+}
+
+fn floor_boundary(text:&str,index:usize)->usize{
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index){
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{paragraphs,decode,encode,Paragraph};
+    use crate::{Config,parse_bytes};
+
+    #[test]
+    fn round_trip(){
+        let text = "a paragraph that is much longer than the width it is wrapped at, so it has to be soft broken\n\n>> a quoted reply that is long enough to be wrapped as well\n plain line starting with a space\nFrom the start of a line\n-- \nsignature";
+        let encoded = encode(text,30);
+        for line in encoded.split("\r\n"){
+            //the space of a soft break may follow a word that fills the width
+            assert!(line.trim_end().len() <= 30,"{:?}",line);
+        }
+        assert!(encoded.contains("\r\n  plain line"));
+        assert!(encoded.contains("\r\n From the start"));
+        assert!(encoded.contains("\r\n-- \r\n"));
+        assert_eq!(decode(&encoded,false),text);
+    }
+
+    #[test]
+    fn delsp_and_depth(){
+        let text = "one  \r\ntwo \r\n> three \r\n> four\r\n>> five\r\n";
+        assert_eq!(paragraphs(text,true)[0],Paragraph{depth:0,text:"one two".to_string()});
+        assert_eq!(paragraphs(text,false)[0].text,"one  two ");
+        //a change of quote depth ends a paragraph even after a soft break
+        assert_eq!(decode(text,false),"one  two \n> three four\n>> five\n");
+    }
+
+    #[test]
+    fn flowed_part(){
+        let input = crate::io::read_file("./gl_yt_alt.txt").unwrap();
+        let email = parse_bytes(&input,&Config::new().unwrap()).unwrap();
+        let part = &email.body[0];
+        assert!(part.is_flowed());
+        let text = part.unflowed().unwrap();
+        assert!(text.starts_with("\u{1f534} Ludwig is live now: YOU HAVE 30 MINUTES TO BUY WHATEVER YOU WANT WITH MY CREDIT CARD\n"));
+        assert!(!email.body[1].is_flowed());
+    }
+
+}
@@ -0,0 +1,551 @@
+///a piece of html as seen by the tokenizer, tag and attribute names are lowercased.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Token{
+    Text(String),
+    Open(String,Vec<(String,String)>,bool),
+    Close(String),
+    Comment(String)
+}
+
+//elements whose content is never rendered as text
+const HIDDEN:[&str;6] = ["script","style","head","title","template","noscript"];
+const BLOCKS:[&str;22] = [
+    "p","div","section","article","header","footer","nav","aside","main","address","center","form",
+    "h1","h2","h3","h4","h5","h6","pre","blockquote","dl","table"
+];
+
+///splits html into text, tags and comments, doctype and processing instructions are skipped.
+///
+///the content of script and style is kept as raw text, broken markup never fails, a "<" that does not start a
+///tag is text.
+pub fn tokenize(html:&str)->Vec<Token>{
+
+    let mut collect:Vec<Token> = vec![];
+    let mut rest = html;
+    let mut raw:Option<String> = None;
+
+    while !rest.is_empty(){
+
+        //script and style run until their close tag
+        if let Some(name) = &raw{
+            let close = format!("</{}",name);
+            let end = find_ignore_case(rest,&close).unwrap_or(rest.len());
+            if end > 0{
+                collect.push(Token::Text(rest[..end].to_string()));
+            }
+            rest = &rest[end..];
+            raw = None;
+            continue;
+        }
+
+        let start = match rest.find('<'){
+            Some(v)=>{v},
+            None=>{
+                collect.push(Token::Text(rest.to_string()));
+                break;
+            }
+        };
+        if start > 0{
+            collect.push(Token::Text(rest[..start].to_string()));
+            rest = &rest[start..];
+        }
+
+        if rest.starts_with("<!--"){
+            let end = rest[4..].find("-->").map(|v|v + 4);
+            match end{
+                Some(v)=>{
+                    collect.push(Token::Comment(rest[4..v].to_string()));
+                    rest = &rest[v+3..];
+                },
+                None=>{
+                    collect.push(Token::Comment(rest[4..].to_string()));
+                    rest = "";
+                }
+            }
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?"){
+            match rest.find('>'){
+                Some(v)=>{rest = &rest[v+1..];},
+                None=>{rest = "";}
+            }
+            continue;
+        }
+
+        let closing = rest.starts_with("</");
+        let name_start = if closing {2} else {1};
+        let name_len = rest[name_start..].find(|c:char|!(c.is_ascii_alphanumeric() || c == '-' || c == ':')).unwrap_or(rest.len() - name_start);
+        if name_len == 0{
+            collect.push(Token::Text("<".to_string()));
+            rest = &rest[1..];
+            continue;
+        }
+        let name = rest[name_start..name_start+name_len].to_lowercase();
+        let (attrs,self_closing,end) = parse_attributes(&rest[name_start+name_len..]);
+        rest = &rest[name_start+name_len+end..];
+
+        if closing{
+            collect.push(Token::Close(name));
+        } else {
+            if (name == "script" || name == "style") && !self_closing{
+                raw = Some(name.clone());
+            }
+            collect.push(Token::Open(name,attrs,self_closing));
+        }
+
+    }
+
+    collect
+
+}
+
+//attributes up to the end of the tag, returns them with the self closing flag and the length consumed
+fn parse_attributes(input:&str)->(Vec<(String,String)>,bool,usize){
+
+    let mut collect:Vec<(String,String)> = vec![];
+    let bytes = input.as_bytes();
+    let mut index = 0;
+    let mut self_closing = false;
+
+    while index < bytes.len(){
+        let c = bytes[index];
+        if c == b'>'{
+            return (collect,self_closing,index + 1);
+        }
+        if c == b'/' || c.is_ascii_whitespace(){
+            self_closing = c == b'/';
+            index += 1;
+            continue;
+        }
+        self_closing = false;
+        let name_start = index;
+        while index < bytes.len() && !bytes[index].is_ascii_whitespace() && bytes[index] != b'=' && bytes[index] != b'>' && bytes[index] != b'/'{
+            index += 1;
+        }
+        let name = input[name_start..index].to_lowercase();
+        while index < bytes.len() && bytes[index].is_ascii_whitespace(){
+            index += 1;
+        }
+        let mut value = String::new();
+        if index < bytes.len() && bytes[index] == b'='{
+            index += 1;
+            while index < bytes.len() && bytes[index].is_ascii_whitespace(){
+                index += 1;
+            }
+            if index < bytes.len() && (bytes[index] == b'"' || bytes[index] == b'\''){
+                let quote = bytes[index];
+                let value_start = index + 1;
+                index = value_start;
+                while index < bytes.len() && bytes[index] != quote{
+                    index += 1;
+                }
+                value = input[value_start..index].to_string();
+                index = (index + 1).min(bytes.len());
+            } else {
+                let value_start = index;
+                while index < bytes.len() && !bytes[index].is_ascii_whitespace() && bytes[index] != b'>'{
+                    index += 1;
+                }
+                value = input[value_start..index].to_string();
+            }
+        }
+        if !name.is_empty(){
+            collect.push((name,decode_entities(&value)));
+        }
+    }
+
+    (collect,self_closing,bytes.len())
+
+}
+
+pub(crate) fn find_ignore_case(haystack:&str,needle:&str)->Option<usize>{
+    let needle = needle.as_bytes();
+    haystack.as_bytes().windows(needle.len()).position(|w|w.eq_ignore_ascii_case(needle))
+}
+
+///resolves named, decimal and hex character references, unknown references are kept as written.
+pub fn decode_entities(input:&str)->String{
+
+    if !input.contains('&'){
+        return input.to_string();
+    }
+
+    let mut collect = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('&'){
+        collect.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest[1..].find(|c:char|!(c.is_ascii_alphanumeric() || c == '#')).map(|v|v + 1).unwrap_or(rest.len());
+        let name = &rest[1..end];
+        let decoded:Option<char> = if let Some(number) = name.strip_prefix('#'){
+            let code = if number.starts_with('x') || number.starts_with('X'){
+                u32::from_str_radix(&number[1..],16).ok()
+            } else {
+                number.parse::<u32>().ok()
+            };
+            code.and_then(std::char::from_u32)
+        } else {
+            named_entity(name)
+        };
+        match decoded{
+            Some(c)=>{
+                collect.push(c);
+                rest = &rest[end..];
+                if rest.starts_with(';'){
+                    rest = &rest[1..];
+                }
+            },
+            None=>{
+                collect.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    collect.push_str(rest);
+    collect
+
+}
+
+fn named_entity(name:&str)->Option<char>{
+    let c = match name{
+        "amp"|"AMP"=>'&',
+        "lt"|"LT"=>'<',
+        "gt"|"GT"=>'>',
+        "quot"|"QUOT"=>'"',
+        "apos"=>'\'',
+        "nbsp"=>'\u{a0}',
+        "copy"=>'©',
+        "reg"=>'®',
+        "trade"=>'™',
+        "hellip"=>'…',
+        "mdash"=>'—',
+        "ndash"=>'–',
+        "lsquo"=>'‘',
+        "rsquo"=>'’',
+        "sbquo"=>'‚',
+        "ldquo"=>'“',
+        "rdquo"=>'”',
+        "bdquo"=>'„',
+        "laquo"=>'«',
+        "raquo"=>'»',
+        "bull"=>'•',
+        "middot"=>'·',
+        "deg"=>'°',
+        "euro"=>'€',
+        "pound"=>'£',
+        "yen"=>'¥',
+        "cent"=>'¢',
+        "sect"=>'§',
+        "para"=>'¶',
+        "times"=>'×',
+        "divide"=>'÷',
+        "plusmn"=>'±',
+        "frac12"=>'½',
+        "frac14"=>'¼',
+        "frac34"=>'¾',
+        "iexcl"=>'¡',
+        "iquest"=>'¿',
+        "shy"=>'\u{ad}',
+        "zwnj"=>'\u{200c}',
+        "zwj"=>'\u{200d}',
+        "ensp"=>'\u{2002}',
+        "emsp"=>'\u{2003}',
+        "thinsp"=>'\u{2009}',
+        "larr"=>'←',
+        "rarr"=>'→',
+        "uarr"=>'↑',
+        "darr"=>'↓',
+        "check"=>'✓',
+        _=>{return None;}
+    };
+    Some(c)
+}
+
+//collects rendered text, whitespace is collapsed and line breaks are only ever added once
+struct Render{
+    out:String,
+    pre:usize,
+    quote:usize
+}
+
+impl Render{
+    fn text(&mut self,text:&str){
+        if self.pre > 0{
+            for (index,line) in text.split('\n').enumerate(){
+                if index > 0{
+                    self.out.push('\n');
+                    self.prefix();
+                }
+                self.out.push_str(line);
+            }
+            return;
+        }
+        for word in text.split(|c:char|c.is_whitespace() && c != '\u{a0}'){
+            if word.is_empty(){
+                self.space();
+                continue;
+            }
+            if self.out.ends_with('\n'){
+                self.prefix();
+            }
+            self.out.push_str(&word.replace('\u{a0}'," "));
+            self.space();
+        }
+        //the last split piece is followed by no whitespace
+        if !text.ends_with(|c:char|c.is_whitespace() && c != '\u{a0}') && self.out.ends_with(' '){
+            self.out.pop();
+        }
+    }
+    fn space(&mut self){
+        if !self.out.is_empty() && !self.out.ends_with(' ') && !self.out.ends_with('\n'){
+            self.out.push(' ');
+        }
+    }
+    fn prefix(&mut self){
+        for _ in 0..self.quote{
+            self.out.push_str("> ");
+        }
+    }
+    fn lines(&mut self,count:usize){
+        if self.out.is_empty(){
+            return;
+        }
+        while self.out.ends_with(' '){
+            self.out.pop();
+        }
+        let present = self.out.len() - self.out.trim_end_matches('\n').len();
+        for _ in present..count{
+            self.out.push('\n');
+        }
+    }
+    //adds text right after the last rendered word, in front of any whitespace
+    fn append(&mut self,text:&str){
+        let end = self.out.trim_end().len();
+        let trailing:String = self.out[end..].chars().filter(|c|*c == '\n').collect();
+        self.out.truncate(end);
+        self.out.push_str(text);
+        self.out.push_str(&trailing);
+    }
+    fn push(&mut self,text:&str){
+        if self.out.ends_with('\n'){
+            self.prefix();
+        }
+        self.out.push_str(text);
+    }
+}
+
+///renders html as readable plain text.
+///
+///scripts, styles and the head are dropped, block elements and line breaks become new lines, links keep their
+///target in brackets, images are replaced by their alt text, lists are rendered with "-" or numbers, table cells
+///are separated with " | " and blockquotes are quoted with "> ".
+///
+/// ```
+/// use letterman_email_body_parser::html;
+///
+/// let text = html::to_text("<p>Hi &amp; welcome</p><ul><li>one</li><li><a href=\"https://a.b\">two</a></li></ul>");
+/// assert_eq!(text,"Hi & welcome\n\n- one\n- two (https://a.b)");
+/// ```
+pub fn to_text(html:&str)->String{
+
+    let mut render = Render{out:String::new(),pre:0,quote:0};
+    let mut hidden:usize = 0;
+    let mut lists:Vec<Option<usize>> = vec![];
+    let mut links:Vec<(String,usize)> = vec![];
+    let mut row_start:usize = 0;
+
+    for token in tokenize(html){
+        match token{
+            Token::Text(text)=>{
+                if hidden == 0{
+                    render.text(&decode_entities(&text));
+                }
+            },
+            Token::Comment(_)=>{},
+            Token::Open(name,attrs,self_closing)=>{
+                let name = name.as_str();
+                if HIDDEN.contains(&name){
+                    if !self_closing{
+                        hidden += 1;
+                    }
+                    continue;
+                }
+                if hidden > 0{
+                    continue;
+                }
+                let attr = |key:&str|attrs.iter().find(|(k,_)|k == key).map(|(_,v)|v.trim().to_string());
+                match name{
+                    "br"=>{
+                        while render.out.ends_with(' '){render.out.pop();}
+                        render.out.push('\n');
+                    },
+                    "hr"=>{
+                        render.lines(1);
+                        render.push("---");
+                        render.lines(1);
+                    },
+                    "ul"|"ol"=>{
+                        render.lines(1);
+                        lists.push(if name == "ol"{Some(0)} else {None});
+                    },
+                    "li"=>{
+                        render.lines(1);
+                        let indent = "  ".repeat(lists.len().saturating_sub(1));
+                        let marker = match lists.last_mut(){
+                            Some(Some(n))=>{
+                                *n += 1;
+                                format!("{}{}. ",indent,n)
+                            },
+                            _=>{format!("{}- ",indent)}
+                        };
+                        render.push(&marker);
+                    },
+                    "tr"=>{
+                        render.lines(1);
+                        row_start = render.out.len();
+                    },
+                    "td"|"th"=>{
+                        //empty cells of layout tables get no separator
+                        let rendered = render.out.trim_end();
+                        if rendered.len() > row_start && !rendered.ends_with('|'){
+                            render.append(" |");
+                            render.space();
+                        }
+                    },
+                    "blockquote"=>{
+                        render.lines(2);
+                        render.quote += 1;
+                    },
+                    "pre"=>{
+                        render.lines(2);
+                        render.pre += 1;
+                    },
+                    "a"=>{
+                        match attr("href"){
+                            Some(href)=>{links.push((href,render.out.len()));},
+                            None=>{links.push((String::new(),render.out.len()));}
+                        }
+                    },
+                    "img"=>{
+                        if let Some(alt) = attr("alt").filter(|v|!v.is_empty()){
+                            render.space();
+                            render.push(&format!("[{}]",alt));
+                            render.space();
+                        }
+                    },
+                    _=>{
+                        if BLOCKS.contains(&name){
+                            render.lines(if name.starts_with('h') || name == "p" {2} else {1});
+                        }
+                    }
+                }
+            },
+            Token::Close(name)=>{
+                let name = name.as_str();
+                if HIDDEN.contains(&name){
+                    hidden = hidden.saturating_sub(1);
+                    continue;
+                }
+                if hidden > 0{
+                    continue;
+                }
+                match name{
+                    "ul"|"ol"=>{
+                        lists.pop();
+                        render.lines(1);
+                    },
+                    "blockquote"=>{
+                        render.quote = render.quote.saturating_sub(1);
+                        render.lines(2);
+                    },
+                    "pre"=>{
+                        render.pre = render.pre.saturating_sub(1);
+                        render.lines(2);
+                    },
+                    "a"=>{
+                        if let Some((href,start)) = links.pop(){
+                            let start = start.min(render.out.len());
+                            let label = render.out[start..].trim().to_string();
+                            let show =
+                                !href.is_empty() && !href.starts_with('#') &&
+                                !href.to_lowercase().starts_with("javascript:") &&
+                                label != href && label != href.trim_start_matches("mailto:");
+                            if show{
+                                if label.is_empty(){
+                                    render.space();
+                                    render.push(&href);
+                                } else {
+                                    render.append(&format!(" ({})",href));
+                                }
+                            }
+                        }
+                    },
+                    _=>{
+                        if BLOCKS.contains(&name){
+                            render.lines(if name.starts_with('h') || name == "p" {2} else {1});
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut collect:Vec<&str> = vec![];
+    let mut blank = 0;
+    for line in render.out.lines(){
+        //a separator in front of trailing empty cells
+        let line = line.trim_end();
+        let line = line.strip_suffix(" |").unwrap_or(line);
+        if line.is_empty(){
+            blank += 1;
+            if blank > 1 || collect.is_empty(){
+                continue;
+            }
+        } else {
+            blank = 0;
+        }
+        collect.push(line);
+    }
+    while collect.last() == Some(&""){
+        collect.pop();
+    }
+    collect.join("\n")
+
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{to_text,decode_entities};
+    use crate::{Config,parse_bytes};
+
+    #[test]
+    fn lists(){
+        let html = "<p>Agenda:</p><ol><li>intro</li><li>plans<ul><li>q1</li><li>q2</li></ul></li><li>close</li></ol><p>end</p>";
+        assert_eq!(to_text(html),"Agenda:\n\n1. intro\n2. plans\n  - q1\n  - q2\n3. close\n\nend");
+    }
+
+    #[test]
+    fn blockquotes(){
+        let html = "<div>On monday Bob wrote:</div><blockquote><p>first</p><blockquote>older<br>reply</blockquote></blockquote><p>thanks</p>";
+        assert_eq!(to_text(html),"On monday Bob wrote:\n\n> first\n\n> > older\n> > reply\n\nthanks");
+    }
+
+    #[test]
+    fn tables_and_hidden(){
+        let html = "<html><head><title>t</title><style>p{color:red}</style></head><body><script>alert(1)</script><table><tr><th>name</th><th>qty</th></tr><tr><td>tea &amp; cake</td><td>2</td></tr></table><a href=\"https://a.b/x\">site</a> <a href=\"#top\">top</a></body></html>";
+        assert_eq!(to_text(html),"name | qty\ntea & cake | 2\nsite (https://a.b/x) top");
+        assert_eq!(decode_entities("&lt;&#65;&#x42;&nbsp;&unknown;"),"<AB\u{a0}&unknown;");
+    }
+
+    #[test]
+    fn text_body_falls_back_to_html(){
+        let raw = "Content-Type: multipart/alternative; boundary=b\r\n\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>only <b>html</b></p>\r\n--b--\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        assert_eq!(email.text_body().unwrap(),"only html");
+        let raw = raw.replace("--b--","--b\r\nContent-Type: text/plain\r\n\r\nplain\r\n--b--");
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        assert_eq!(email.text_body().unwrap(),"plain");
+    }
+
+}
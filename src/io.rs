@@ -2,6 +2,7 @@
 use std::io::Read;
 use std::fs::File;
 
+#[allow(clippy::needless_return)]
 pub fn read_string(path:&'static str)->Result<String,&'static str>{
 
     match read_file(path){
@@ -22,6 +23,7 @@ pub fn read_string(path:&'static str)->Result<String,&'static str>{
 
 }
 
+#[allow(clippy::needless_return)]
 pub fn read_file(path:&'static str)->Result<Vec<u8>,&'static str>{
 
     let mut file:File;
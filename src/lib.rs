@@ -1,23 +1,43 @@
 
-
 pub mod io;
 mod config;
 mod parser;
 mod part;
+pub mod address;
+pub mod date;
+pub mod received;
+pub mod mime;
 pub mod dkim;
+pub mod stream;
+pub mod reader;
+pub mod decode;
+pub mod flowed;
+pub mod html;
+pub mod sanitize;
+pub mod sniff;
+pub mod digest;
 
 //./gl_alt_atch.txt
 //./sldv_atch.txt
 //../letterman_tools/emails/sldv_atch.txt
 
-pub use config::{Config,PartHandler,EmailBody,Dkim,ContentEncoding,ContentDecoded,Part};
 
-///this is a tokio based email body parser and dkim validator, the input should not include the data end flag "\r\n.\r\n",dkim keys are cached in a tokio RwLock for fatser reads, if no dkim is provided validation returns successfull.
+pub use config::{Config,PartHandler,EmailBody,Dkim,ContentEncoding,ContentDecoded,Part,ContentDisposition,DispositionKind,LineEnding,Related};
+pub use address::Mailbox;
+pub use date::EmailDate;
+pub use received::Hop;
+pub use mime::PartRef;
+pub use stream::{StreamParser,Event};
+pub use reader::{parse_reader,parse_reader_sink,DataCodec,unstuff};
+
+///this is a tokio based email body parser and dkim validator, the input should not include the data end flag "\r\n.\r\n" unless `Config::dot_stuffed` is set,dkim keys are cached in a tokio RwLock for fatser reads, if no dkim is provided validation returns successfull.
 /// 
 /// dkim validation functions are exposed for use in dkim module, all required dkim functions are functional, rsa operations are handled via openssl verifier.
 /// 
 /// dns queries are performed with asynresolver in trust_dns_resolver.
 /// 
+/// `parse_reader` parses straight from a tokio reader and `DataCodec` reads smtp DATA including the end flag and dot stuffing.
+/// 
 /// ```
 /// 
 /// use letterman_email_body_parser::{init,Config,io};
@@ -66,142 +86,95 @@ pub use config::{Config,PartHandler,EmailBody,Dkim,ContentEncoding,ContentDecode
 /// }
 /// ```
 pub fn init(lines:Vec<&str>,config:&Config)->Result<EmailBody,&'static str>{
+    let input = lines.join("\r\n");
+    parse_bytes(input.as_bytes(),config)
+}
 
-    let mut data_started:bool = false;
-    let mut boundry_started = false;
-    let mut boundaries:Vec<String> = vec![];
-    let mut part_handler = PartHandler::new();
-    let mut body = EmailBody::new();
+///parses a raw message without requiring it to be valid utf-8, 8bit and binary bodies are kept as bytes.
+///
+///crlf, bare lf and mixed line endings are accepted and the style is recorded in `EmailBody::line_ending`,
+///`Part::offsets` and `EmailBody::body_offset` are byte offsets into the input so the undecoded bodies can be sliced out of it exactly as they were received.
+///with `Config::dot_stuffed` the input is unstuffed first and the offsets point into the unstuffed data.
+///
+/// ```
+/// use letterman_email_body_parser::{parse_bytes,Config,io};
+///
+/// let input = io::read_file("./sldv_atch.txt").unwrap();
+/// let conf = Config::new().unwrap();
+/// let email = parse_bytes(&input,&conf).unwrap();
+/// println!("{:?}",email.headers.get("subject"));
+/// ```
+pub fn parse_bytes(input:&[u8],config:&Config)->Result<EmailBody,&'static str>{
+    if config.dot_stuffed{
+        return parse_input(&unstuff(input),config,0);
+    }
+    parse_input(input,config,0)
+}
 
-    for i in lines{
+fn parse_input(input:&[u8],config:&Config,depth:usize)->Result<EmailBody,&'static str>{
+    let mut stream = StreamParser::nested(config,depth);
+    stream.feed(input)?;
+    stream.finish()
+}
 
-        if i.contains("DKIM-Signature") && body.dkim_found == false{
-            match parser::parse_only_features(&config,i){
-                Ok(v)=>{
-                    body.dkim.overtake(v.0,v.2);
-                    body.dkim_found = true;
-                },
-                Err(_e)=>{
-                    // println!("invalid-DKIM_Signature : {:?}",_e);
-                    return Err("invalid-DKIM_Signature");
-                }
-            }
-            match parser::parse_keyval(&config, i){
-                Ok(hold)=>{
-                    body.header(hold.0,hold.1);
-                },
-                Err(_)=>{}
-            }
-        } else if i.contains("Content-Type"){
-            match parser::parse_content_type(&config,i){
-                Ok(ct)=>{
-                    if ct.0.contains("multipart"){
-                        match ct.1.get("boundary"){
-                            Some(b)=>{
-                                boundaries.push(b.to_string());
-                            },
-                            None=>{
-                                return Err("not_found-boundry-Content-Type");
-                            }
-                        }
-                    }
-                    match parser::parse_keyval(&config, i){
-                        Ok(hold)=>{
-                            if !boundry_started{
-                                body.header(hold.0,hold.1);
-                                body.content_type = ct;
-                            } else {
-                                part_handler.content_feature(hold.0,hold.1);
-                                part_handler.content_type(ct);
-                            }
-                        },
-                        Err(_)=>{}
-                    }
-                    data_started = false;
-                },
-                Err(_)=>{
-                    if !data_started{   
-                        return Err("failed-parse-Content-Type");
-                    } else {
-                        part_handler.data(i.to_string());
-                    }
-                }
-            }
-        } else if i.len() > 0 && !data_started {
-            match parser::parse_keyval(&config,i){
-                Ok(v)=>{
-                    if boundry_started{
-                        part_handler.content_feature(v.0, v.1);
-                    } else {
-                        body.header(v.0,v.1);
-                    }
-                },
-                Err(_)=>{
-                    return Err("failed-parse-keyval");
-                }
-            }
-        } else if i.len() == 0{//empty flag
-            data_started = true;
-            boundry_started = false;
-        } else if i.len() > 0{//check if data should be processed
-            if data_started{
-                //check if data is boundary
-                match config.boundary_regex.captures(i){
-                    Some(captures)=>{
-                        //get boundary
-                        match captures.get(1){
-                            Some(boundary_m)=>{
-                                let boundary = boundary_m.as_str();
-                                //get boundary end flag
-                                match captures.get(2){
-                                    Some(c)=>{
-                                        let len = c.end() - c.start();
-                                        if len > 0{
-                                            //boundary end
-                                            match boundaries.pop(){
-                                                Some(b)=>{
-                                                    if b != boundary{
-                                                        return Err("invalid_boundary_end-diff");
-                                                    } 
-                                                },
-                                                None=>{
-                                                    return Err("invalid_boundary_end-overflow");
-                                                }
-                                            }
-                                        } else {
-                                            //boundary start = no boundry matched
-                                            boundry_started = true;
-                                            part_handler.flush();
-                                        }
-                                    },
-                                    None=>{
-                                        //boundary start
-                                        boundry_started = true;
-                                        part_handler.flush();
-                                    }
-                                }//get boundary end flag
-                            },
-                            None=>{}
-                        }//get boundary
-                    },
-                    None=>{
-                        //data is not boundary
-                        part_handler.data(i.to_string());
-                    }
-                }//check if data is boundary
-            }//check if data should be processed
-        }//if its data type
+//message/rfc822 and message/global parts are parsed into nested emails, text/rfc822-headers into a headers only email,
+//a nested message that fails to parse is left as a plain part
+pub(crate) fn attach_messages(body:&mut EmailBody,config:&Config,depth:usize){
+    for part in body.body.iter_mut().chain(body.attachments.iter_mut()){
+        if !part.is_message(){
+            continue;
+        }
+        let input:&[u8] = match &part.decoded{
+            ContentDecoded::String(v)|ContentDecoded::Html(v)=>{v.as_bytes()},
+            ContentDecoded::Base64(v)|ContentDecoded::Qp(v)|ContentDecoded::Binary(v)=>{v},
+            ContentDecoded::None=>{continue;}
+        };
+        if let Ok(v) = parse_input(input,config,depth + 1){part.message = Some(Box::new(v));}
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{Config,parse_bytes,ContentDecoded};
 
+    const FORWARD:&str = "Subject: Fwd: report\r\nContent-Type: multipart/mixed; boundary=outer\r\n\r\n--outer\r\nContent-Type: text/plain\r\n\r\nsee below\r\n--outer\r\nContent-Type: message/rfc822\r\n\r\nFrom: a@x.org\r\nSubject: report\r\nContent-Type: multipart/mixed; boundary=inner\r\n\r\n--inner\r\nContent-Type: text/plain\r\n\r\nnumbers\r\n--inner\r\nContent-Type: application/pdf\r\nContent-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQK\r\n--inner--\r\n--outer\r\nContent-Type: text/rfc822-headers\r\n\r\nSubject: bounced\r\nTo: b@y.org\r\n--outer--\r\n";
+
+    #[test]
+    fn nested_messages(){
+        let email = parse_bytes(FORWARD.as_bytes(),&Config::new().unwrap()).unwrap();
+        let messages = email.messages();
+        assert_eq!(messages.len(),2);
+        let headers_only = messages.iter().find(|m|m.body.is_empty()).unwrap();
+        assert_eq!(headers_only.headers.get("subject").map(|v|v.as_str()),Some("bounced"));
+        let forwarded = messages.iter().find(|m|!m.body.is_empty()).unwrap();
+        assert_eq!(forwarded.headers.get("subject").map(|v|v.as_str()),Some("report"));
+        assert_eq!(forwarded.from[0].local,"a");
+        assert!(matches!(&forwarded.body[0].decoded,ContentDecoded::String(v) if v == "numbers"));
+        assert_eq!(forwarded.attachments[0].content_type.0,"application/pdf");
     }
 
-    match body.parts(part_handler){
-        Ok(_)=>{
-            return Ok(body);
-        },
-        Err(_e)=>{
-            println!("!!! failed-parse_parts : {:?}",_e);
-            return Err("failed-parse_parts");
-        }
+    #[test]
+    fn raw_octets(){
+        let mut raw:Vec<u8> = b"Subject: caf\xe9\r\nContent-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain; charset=iso-8859-1\r\nContent-Transfer-Encoding: 8bit\r\n\r\ncaf\xe9\r\n--b\r\nContent-Type: application/octet-stream\r\nContent-Transfer-Encoding: binary\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[0,159,255,10,13]);
+        raw.extend_from_slice(b"\r\n--b--\r\n");
+        let email = parse_bytes(&raw,&Config::new().unwrap()).unwrap();
+        assert!(matches!(&email.body[0].decoded,ContentDecoded::String(v) if v == "caf\u{e9}"));
+        let binary = &email.attachments[0];
+        assert_eq!(binary.bytes(),Some(&[0,159,255,10,13][..]));
+        assert_eq!(&raw[binary.offsets.0..binary.offsets.1],&[0,159,255,10,13][..]);
+        let text = &email.body[0];
+        assert_eq!(&raw[text.offsets.0..text.offsets.1],b"caf\xe9");
+        assert!(raw[email.body_offset..].starts_with(b"--b\r\n"));
+    }
+
+    #[test]
+    fn nesting_limit(){
+        let mut config = Config::new().unwrap();
+        config.max_nesting = 0;
+        let email = parse_bytes(FORWARD.as_bytes(),&config).unwrap();
+        assert!(email.messages().is_empty());
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+///where a leaf's decoded part ended up after classification.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PartRef{
+    Body(usize),Attachment(usize)
+}
+
+///a node of the mime structure, multipart nodes carry their boundary and children, leaves point at their part.
+#[derive(Debug,Clone)]
+pub struct MimeNode{
+    pub content_type:(String,HashMap<String,String>,Vec<String>),
+    pub parent_content_type:Option<String>,
+    pub boundary:Option<String>,
+    pub depth:usize,
+    ///position of the leaf among all leaves in document order.
+    pub index:Option<usize>,
+    pub part:Option<PartRef>,
+    ///text between the multipart headers and the first boundary, it is not part of any child.
+    pub preamble:Option<Vec<u8>>,
+    ///text after the close delimiter.
+    pub epilogue:Option<Vec<u8>>,
+    pub children:Vec<MimeNode>
+}
+
+impl MimeNode{
+    pub fn new(content_type:(String,HashMap<String,String>,Vec<String>),parent_content_type:Option<String>,depth:usize)->MimeNode{
+        let mut boundary:Option<String> = None;
+        if content_type.0.starts_with("multipart/"){
+            boundary = content_type.1.get("boundary").cloned();
+        }
+        MimeNode{
+            content_type,
+            parent_content_type,
+            boundary,
+            depth,
+            index:None,
+            part:None,
+            preamble:None,
+            epilogue:None,
+            children:Vec::new()
+        }
+    }
+    pub fn is_multipart(&self)->bool{
+        self.boundary.is_some()
+    }
+    pub fn is_leaf(&self)->bool{
+        self.children.is_empty() && !self.is_multipart()
+    }
+    ///every node below and including this one in document order.
+    pub fn descendants(&self)->Vec<&MimeNode>{
+        let mut collect = vec![self];
+        for child in self.children.iter(){
+            collect.append(&mut child.descendants());
+        }
+        collect
+    }
+    ///leaf nodes in document order.
+    pub fn leaves(&self)->Vec<&MimeNode>{
+        self.descendants().into_iter().filter(|n|n.is_leaf()).collect()
+    }
+    ///nodes whose content type starts with the given prefix, like `multipart/alternative` or `image/`.
+    pub fn find_all(&self,prefix:&str)->Vec<&MimeNode>{
+        let prefix = prefix.to_lowercase();
+        self.descendants().into_iter().filter(|n|n.content_type.0.starts_with(&prefix)).collect()
+    }
+    ///the node holding the leaf with the given index.
+    pub fn find_leaf(&self,index:usize)->Option<&MimeNode>{
+        self.descendants().into_iter().find(|n|n.index == Some(index))
+    }
+    ///the parent of the leaf with the given index.
+    pub fn parent_of(&self,index:usize)->Option<&MimeNode>{
+        self.descendants().into_iter().find(|n|n.children.iter().any(|c|c.index == Some(index)))
+    }
+    pub(crate) fn link(&mut self,refs:&[PartRef]){
+        if let Some(i) = self.index{self.part = refs.get(i).copied();}
+        for child in self.children.iter_mut(){
+            child.link(refs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use crate::{Config,parse_bytes,ContentDecoded,PartRef};
+
+    const RAW:&str = "Content-Type: multipart/mixed; boundary=b\r\n\r\nThis is a multi-part message.\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nhi\r\n--b--\r\ntrailer one\r\ntrailer two\r\n";
+
+    #[test]
+    fn preamble_and_epilogue(){
+        let email = parse_bytes(RAW.as_bytes(),&Config::new().unwrap()).unwrap();
+        assert_eq!(email.tree.preamble.as_deref(),Some(&b"This is a multi-part message.\r\n"[..]));
+        assert_eq!(email.tree.epilogue.as_deref(),Some(&b"trailer one\r\ntrailer two\r\n"[..]));
+        assert!(matches!(&email.body[0].decoded,ContentDecoded::String(v) if v == "hi"));
+    }
+
+    #[test]
+    fn tree(){
+        let email = parse_bytes(RAW.as_bytes(),&Config::new().unwrap()).unwrap();
+        assert!(email.tree.is_multipart());
+        assert_eq!(email.tree.leaves().len(),1);
+        let leaf = email.tree.find_leaf(0).unwrap();
+        assert_eq!(leaf.part,Some(PartRef::Body(0)));
+        assert_eq!(leaf.parent_content_type.as_deref(),Some("multipart/mixed"));
+        assert_eq!(email.tree.find_all("text/").len(),1);
+        assert!(email.tree.parent_of(0).unwrap().is_multipart());
+    }
+
+}
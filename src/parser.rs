@@ -1,7 +1,51 @@
-
 use crate::Config;
 use std::collections::HashMap;
+use encoding_rs::{Encoding,UTF_8,WINDOWS_1252};
+
+///iterates over the lines of raw input, yielding the line, its ending (crlf or a bare lf) and its byte offset.
+///
+///input ending with a line break yields a final empty line so that the data can be rebuilt exactly.
+pub struct Lines<'a>{
+    input:&'a [u8],
+    position:usize,
+    done:bool
+}
+
+impl<'a> Lines<'a>{
+    pub fn new(input:&'a [u8])->Lines<'a>{
+        Lines{
+            input,
+            position:0,
+            done:false
+        }
+    }
+}
+
+impl<'a> Iterator for Lines<'a>{
+    type Item = (&'a [u8],&'a [u8],usize);
+    fn next(&mut self)->Option<Self::Item>{
+        if self.done{
+            return None;
+        }
+        let start = self.position;
+        let rest = &self.input[start..];
+        match rest.iter().position(|b|*b == b'\n'){
+            Some(index)=>{
+                self.position = start + index + 1;
+                if index > 0 && rest[index-1] == b'\r'{
+                    return Some((&rest[..index-1],&rest[index-1..index+1],start));
+                }
+                Some((&rest[..index],&rest[index..index+1],start))
+            },
+            None=>{
+                self.done = true;
+                Some((rest,&rest[rest.len()..],start))
+            }
+        }
+    }
+}
 
+#[allow(clippy::len_zero,clippy::needless_return,clippy::type_complexity)]
 pub fn parse_only_features(config:&Config,line:&str)->Result<(HashMap<String,String>,Vec<String>,Vec<String>),&'static str>{
 
     let features_string:&str;
@@ -19,38 +63,23 @@ pub fn parse_only_features(config:&Config,line:&str)->Result<(HashMap<String,Str
         }
     }
 
-    let features:Vec<&str> = features_string.split(";").collect();
     let mut collect_features:HashMap<String,String> = HashMap::new();
     let mut collect_flags:Vec<String> = vec![];
     let mut collect_feature_order:Vec<String> = vec![];
 
-    for feature in features{
-        match config.feature_regex.captures(feature){
-            Some(captures)=>{
-                match captures.get(1){
-                    Some(v)=>{
-                        let k = v.as_str().to_string();
-                        match captures.get(2){
-                            Some(d)=>{
-                                let vl = d.as_str().to_string();
-                                collect_feature_order.push(k.clone());
-                                match collect_features.insert(k,vl){
-                                    Some(_)=>{},
-                                    None=>{}
-                                }
-                            },
-                            None=>{
-                                collect_flags.push(feature.to_string());
-                            }
-                        }
-                    },
-                    None=>{
-                        collect_flags.push(feature.to_string());
-                    }
+    //dkim tag-lists (rfc 6376 3.2) have no quoting or comments, tag names are case sensitive
+    for feature in features_string.split(';'){
+        match split_tag(feature){
+            Some((k,vl))=>{
+                if !collect_features.contains_key(&k){
+                    collect_feature_order.push(k.clone());
                 }
+                collect_features.insert(k,vl);
             },
             None=>{
-                collect_flags.push(feature.to_string());
+                if feature.trim().len() > 0{
+                    collect_flags.push(feature.to_string());
+                }
             }
         }
     }
@@ -59,6 +88,7 @@ pub fn parse_only_features(config:&Config,line:&str)->Result<(HashMap<String,Str
 
 }
 
+#[allow(clippy::type_complexity,clippy::needless_return)]
 pub fn parse_content_type(config:&Config,line:&str)->
     Result<(String,HashMap<String,String>,Vec<String>),&'static str>
 {
@@ -78,48 +108,286 @@ pub fn parse_content_type(config:&Config,line:&str)->
         }
     }
 
-    let mut features:Vec<&str> = features_string.split(";").collect();
-    let value = features.remove(0);
+    return Ok(parse_params(features_string));
+
+}
+
+///parses a structured mime header value like `text/plain; charset="utf-8"` into its lowercased value, parameters and unparsable flags.
+///
+///quoted-strings, escaped quotes, comments and rfc 2231 extended / continued parameters are supported, parameter names are lowercased.
+pub fn parse_params(input:&str)->(String,HashMap<String,String>,Vec<String>){
+
+    let mut features = split_unquoted(input,';');
+    let value = strip_comments(&features.remove(0)).trim().to_lowercase();
     let mut collect_features:HashMap<String,String> = HashMap::new();
     let mut collect_flags:Vec<String> = vec![];
+    let mut extended:Vec<(String,usize,bool,String)> = vec![];
 
     for feature in features{
-        match config.feature_regex.captures(feature){
-            Some(captures)=>{
-                match captures.get(1){
-                    Some(v)=>{
-                        let k = v.as_str().to_string();
-                        match captures.get(2){
-                            Some(d)=>{
-                                let mut vl = d.as_str().trim_end().to_string();
-                                let last = vl.pop().unwrap();
-                                if last == '"'{
-                                    collect_features.insert(k,vl);
-                                } else {
-                                    vl.push(last);
-                                    collect_features.insert(k,vl);
-                                }
-                            },
-                            None=>{
-                                collect_flags.push(feature.to_string());
-                            }
-                        }
+        let clean = strip_comments(&feature);
+        if clean.trim().is_empty(){
+            continue;
+        }
+        match split_param(&clean){
+            Some((k,vl))=>{
+                match parse_extended_name(&k){
+                    Some((base,section,encoded))=>{
+                        extended.push((base,section,encoded,vl));
                     },
                     None=>{
-                        collect_flags.push(feature.to_string());
+                        collect_features.insert(k,vl);
                     }
                 }
             },
             None=>{
-                collect_flags.push(feature.to_string());
+                collect_flags.push(feature.trim().to_string());
+            }
+        }
+    }
+
+    for (k,vl) in join_extended(extended){
+        collect_features.insert(k,vl);
+    }
+
+    (value,collect_features,collect_flags)
+
+}
+
+///splits a header value on the delimiter, delimiters inside quoted-strings and comments are kept.
+pub fn split_unquoted(input:&str,delimiter:char)->Vec<String>{
+
+    let mut collect:Vec<String> = vec![];
+    let mut current = String::new();
+    let mut in_quote = false;
+    let mut escaped = false;
+    let mut comment_depth:usize = 0;
+
+    for c in input.chars(){
+        if escaped{
+            escaped = false;
+        } else if c == '\\' && (in_quote || comment_depth > 0){
+            escaped = true;
+        } else if in_quote{
+            if c == '"'{in_quote = false;}
+        } else if c == '"' && comment_depth == 0{
+            in_quote = true;
+        } else if c == '('{
+            comment_depth += 1;
+        } else if c == ')' && comment_depth > 0{
+            comment_depth -= 1;
+        } else if c == delimiter && comment_depth == 0{
+            collect.push(current);
+            current = String::new();
+            continue;
+        }
+        current.push(c);
+    }
+
+    collect.push(current);
+    collect
+
+}
+
+///removes rfc 5322 comments (nested and escaped) that are outside of quoted-strings, each comment is replaced by a space.
+pub fn strip_comments(input:&str)->String{
+
+    let mut collect = String::new();
+    let mut in_quote = false;
+    let mut escaped = false;
+    let mut comment_depth:usize = 0;
+
+    for c in input.chars(){
+        if comment_depth > 0{
+            if escaped{escaped = false;} else if c == '\\'{escaped = true;} else if c == '('{comment_depth += 1;} else if c == ')'{
+                comment_depth -= 1;
+                if comment_depth == 0{collect.push(' ');}
             }
+            continue;
+        }
+        if escaped{
+            escaped = false;
+        } else if in_quote{
+            if c == '\\'{escaped = true;} else if c == '"'{in_quote = false;}
+        } else if c == '"'{
+            in_quote = true;
+        } else if c == '('{
+            comment_depth = 1;
+            continue;
         }
+        collect.push(c);
     }
 
-    return Ok((value.to_string(),collect_features,collect_flags));
+    collect
 
 }
 
+///removes the surrounding quotes of a quoted-string and resolves its escapes, unquoted values are only trimmed.
+pub fn unquote(input:&str)->String{
+
+    let input = input.trim();
+    if !input.starts_with('"'){
+        return input.to_string();
+    }
+
+    let mut collect = String::new();
+    let mut escaped = false;
+    for c in input.chars().skip(1){
+        if escaped{
+            collect.push(c);
+            escaped = false;
+        } else if c == '\\'{
+            escaped = true;
+        } else if c == '"'{
+            break;
+        } else {
+            collect.push(c);
+        }
+    }
+
+    collect
+
+}
+
+fn is_token(name:&str)->bool{
+    !name.is_empty() && name.chars().all(|c|{
+        c.is_ascii() && !c.is_ascii_control() && !" ()<>@,;:\\\"/[]?=".contains(c)
+    })
+}
+
+fn split_param(feature:&str)->Option<(String,String)>{
+    let index = feature.find('=')?;
+    let name = feature[..index].trim().to_lowercase();
+    if !is_token(&name){
+        return None;
+    }
+    Some((name,unquote(&feature[index+1..])))
+}
+
+fn split_tag(feature:&str)->Option<(String,String)>{
+    let index = feature.find('=')?;
+    let name = feature[..index].trim();
+    if name.is_empty() || !name.chars().all(|c|c.is_ascii_alphanumeric() || c == '_'){
+        return None;
+    }
+    Some((name.to_string(),feature[index+1..].trim().to_string()))
+}
+
+//rfc 2231 names look like name*, name*0, name*0* or name*1
+fn parse_extended_name(name:&str)->Option<(String,usize,bool)>{
+    let index = name.find('*')?;
+    let base = name[..index].to_string();
+    let mut rest = &name[index+1..];
+    let encoded = rest.ends_with('*') || rest.is_empty();
+    if rest.ends_with('*'){
+        rest = &rest[..rest.len()-1];
+    }
+    let section:usize;
+    if rest.is_empty(){
+        section = 0;
+    } else {
+        match rest.parse::<usize>(){
+            Ok(v)=>{section = v;},
+            Err(_)=>{return None;}
+        }
+    }
+    Some((base,section,encoded))
+}
+
+fn join_extended(mut extended:Vec<(String,usize,bool,String)>)->Vec<(String,String)>{
+
+    let mut order:Vec<String> = vec![];
+    for e in extended.iter(){
+        if !order.contains(&e.0){
+            order.push(e.0.clone());
+        }
+    }
+    extended.sort_by_key(|e|e.1);
+
+    let mut collect:Vec<(String,String)> = vec![];
+    for name in order{
+        let mut charset = String::new();
+        let mut buffer:Vec<u8> = vec![];
+        for (base,section,encoded,value) in extended.iter(){
+            if base != &name{
+                continue;
+            }
+            if !encoded{
+                buffer.extend_from_slice(value.as_bytes());
+                continue;
+            }
+            let mut value = value.as_str();
+            if *section == 0{
+                let hold:Vec<&str> = value.splitn(3,'\'').collect();
+                if hold.len() == 3{
+                    charset = hold[0].to_lowercase();
+                    value = hold[2];
+                }
+            }
+            buffer.append(&mut percent_decode(value));
+        }
+        collect.push((name,decode_charset(&charset,buffer)));
+    }
+
+    collect
+
+}
+
+fn percent_decode(value:&str)->Vec<u8>{
+    let bytes = value.as_bytes();
+    let mut collect:Vec<u8> = vec![];
+    let mut index = 0;
+    while index < bytes.len(){
+        if bytes[index] == b'%' && index + 2 < bytes.len() && value.is_char_boundary(index+3){
+            if let Ok(v) = u8::from_str_radix(&value[index+1..index+3],16){
+                collect.push(v);
+                index += 3;
+                continue;
+            }
+        }
+        collect.push(bytes[index]);
+        index += 1;
+    }
+    collect
+}
+
+const ASCII_LABELS:[&str;10] = [
+    "us-ascii","ascii","ansi_x3.4-1968","ansi_x3.4-1986","iso646-us","iso-ir-6","us","cp367","ibm367","csascii"
+];
+
+pub fn decode_charset(charset:&str,buffer:Vec<u8>)->String{
+    decode_text(Some(charset),&buffer).0
+}
+
+///decodes text in the given charset, the flag is true when the conversion was lossy.
+///
+///unknown charsets and text that is not valid in the charset it is labeled with (mostly latin text sent as
+///utf-8 or us-ascii) is read as utf-8 if valid and as windows-1252 otherwise, which never fails. text that had
+///to be read in another charset than the declared one is flagged too.
+pub fn decode_text(charset:Option<&str>,buffer:&[u8])->(String,bool){
+    let label = charset.map(|c|c.trim().trim_matches('"').to_lowercase());
+    let encoding = label.as_ref().and_then(|c|Encoding::for_label(c.as_bytes()));
+    //encoding_rs reads ascii labels as windows-1252, but 8 bit text labeled ascii is far more often utf-8
+    let ascii = match &label{
+        Some(v)=>{ASCII_LABELS.contains(&v.as_str())},
+        None=>{false}
+    };
+    if let Some(e) = encoding{
+        if ascii && buffer.is_ascii(){
+            return (String::from_utf8_lossy(buffer).to_string(),false);
+        }
+        if e != UTF_8 && !ascii{
+            let (decoded,lossy) = e.decode_without_bom_handling(buffer);
+            return (decoded.to_string(),lossy);
+        }
+    }
+    //a declared charset other than utf-8 is overridden from here on
+    let overridden = label.is_some() && encoding != Some(UTF_8);
+    if let Ok(v) = std::str::from_utf8(buffer){return (v.to_string(),overridden && !buffer.is_ascii());}
+    let (decoded,_) = WINDOWS_1252.decode_without_bom_handling(buffer);
+    (decoded.to_string(),true)
+}
+
+#[allow(clippy::needless_late_init,clippy::needless_return)]
 pub fn parse_keyval(config:&Config,line:&str)->Result<(String,String),&'static str>{
 
     match config.keyval_regex.captures(line){
@@ -145,4 +413,151 @@ pub fn parse_keyval(config:&Config,line:&str)->Result<(String,String),&'static s
         }
     }
 
-}
\ No newline at end of file
+}
+
+///decodes rfc 2047 encoded-words (`=?charset?B?...?=` and `=?charset?Q?...?=`), whitespace between adjacent encoded-words is dropped.
+pub fn decode_encoded_words(input:&str)->String{
+
+    let mut collect = String::new();
+    let mut rest = input;
+    let mut last_was_encoded = false;
+
+    loop{
+        let start:usize = match rest.find("=?"){
+            Some(v)=>{v},
+            None=>{
+                collect.push_str(rest);
+                break;
+            }
+        };
+        match decode_encoded_word(&rest[start..]){
+            Some((decoded,len))=>{
+                let between = &rest[..start];
+                if !(last_was_encoded && between.trim().is_empty()){
+                    collect.push_str(between);
+                }
+                collect.push_str(&decoded);
+                rest = &rest[start+len..];
+                last_was_encoded = true;
+            },
+            None=>{
+                collect.push_str(&rest[..start+2]);
+                rest = &rest[start+2..];
+                last_was_encoded = false;
+            }
+        }
+    }
+
+    collect
+
+}
+
+//returns the decoded text and the length of the encoded-word
+fn decode_encoded_word(input:&str)->Option<(String,usize)>{
+
+    let inner = &input[2..];
+    let charset_end = inner.find('?')?;
+    let charset = &inner[..charset_end];
+    let after_charset = &inner[charset_end+1..];
+    let encoding_end = after_charset.find('?')?;
+    let encoding = &after_charset[..encoding_end];
+    let text_start = &after_charset[encoding_end+1..];
+    let text_end = text_start.find("?=")?;
+    let text = &text_start[..text_end];
+    if charset.is_empty() || text.contains(' '){
+        return None;
+    }
+
+    //rfc 2231 allows a language suffix like utf-8*en
+    let charset = charset.split('*').next().unwrap_or("").to_lowercase();
+
+    let buffer:Vec<u8>;
+    if encoding.eq_ignore_ascii_case("b"){
+        match base64::decode(text){
+            Ok(v)=>{buffer = v;},
+            Err(_)=>{return None;}
+        }
+    } else if encoding.eq_ignore_ascii_case("q"){
+        buffer = q_decode(text);
+    } else {
+        return None;
+    }
+
+    let len = 2 + charset_end + 1 + encoding_end + 1 + text_end + 2;
+    Some((decode_charset(&charset,buffer),len))
+
+}
+
+fn q_decode(text:&str)->Vec<u8>{
+    let bytes = text.as_bytes();
+    let mut collect:Vec<u8> = vec![];
+    let mut index = 0;
+    while index < bytes.len(){
+        if bytes[index] == b'_'{
+            collect.push(b' ');
+        } else if bytes[index] == b'=' && index + 2 < bytes.len() && text.is_char_boundary(index+3){
+            match u8::from_str_radix(&text[index+1..index+3],16){
+                Ok(v)=>{
+                    collect.push(v);
+                    index += 3;
+                    continue;
+                },
+                Err(_)=>{collect.push(b'=');}
+            }
+        } else {
+            collect.push(bytes[index]);
+        }
+        index += 1;
+    }
+    collect
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{decode_text,parse_params,decode_encoded_words};
+
+    #[test]
+    fn ascii_labels(){
+        assert_eq!(decode_text(Some("us-ascii"),b"cafe"),("cafe".to_string(),false));
+        assert_eq!(decode_text(Some("US-ASCII"),"caf\u{e9}".as_bytes()),("caf\u{e9}".to_string(),true));
+        assert_eq!(decode_text(Some("\"ascii\""),b"caf\xe9"),("caf\u{e9}".to_string(),true));
+    }
+
+    #[test]
+    fn declared_charsets(){
+        assert_eq!(decode_text(Some("iso-8859-1"),b"caf\xe9"),("caf\u{e9}".to_string(),false));
+        assert_eq!(decode_text(Some("utf-8"),"caf\u{e9}".as_bytes()),("caf\u{e9}".to_string(),false));
+        assert_eq!(decode_text(Some("utf-8"),b"caf\xe9"),("caf\u{e9}".to_string(),true));
+        assert_eq!(decode_text(None,"caf\u{e9}".as_bytes()),("caf\u{e9}".to_string(),false));
+        assert_eq!(decode_text(Some("x-unknown"),b"cafe"),("cafe".to_string(),false));
+        assert_eq!(decode_text(Some("x-unknown"),"caf\u{e9}".as_bytes()),("caf\u{e9}".to_string(),true));
+    }
+
+    #[test]
+    fn params(){
+        let (value,params,flags) = parse_params("Text/Plain; charset=\"utf-8\" (comment); name=\"a;b \\\"c\\\".txt\"; FORMAT=flowed; broken");
+        assert_eq!(value,"text/plain");
+        assert_eq!(params.get("charset").map(|v|v.as_str()),Some("utf-8"));
+        assert_eq!(params.get("name").map(|v|v.as_str()),Some("a;b \"c\".txt"));
+        assert_eq!(params.get("format").map(|v|v.as_str()),Some("flowed"));
+        assert_eq!(flags,vec!["broken".to_string()]);
+    }
+
+    #[test]
+    fn continued_params(){
+        let (_,params,_) = parse_params("attachment; filename*1*=%20rates.txt; filename*0*=utf-8'en'%E2%82%AC; title*0=\"part \"; title*1=two");
+        assert_eq!(params.get("filename").map(|v|v.as_str()),Some("\u{20ac} rates.txt"));
+        assert_eq!(params.get("title").map(|v|v.as_str()),Some("part two"));
+        let (_,params,_) = parse_params("attachment; filename*=iso-8859-1''caf%E9.txt");
+        assert_eq!(params.get("filename").map(|v|v.as_str()),Some("caf\u{e9}.txt"));
+    }
+
+    #[test]
+    fn encoded_words(){
+        assert_eq!(decode_encoded_words("=?utf-8?B?Y2Fmw6k=?= =?ISO-8859-1?Q?au_lait=E9?= ok"),"caf\u{e9}au lait\u{e9} ok");
+        assert_eq!(decode_encoded_words("Re: =?utf-8*en?q?hi?= there"),"Re: hi there");
+        assert_eq!(decode_encoded_words("=?utf-8?x?abc?= and =?broken"),"=?utf-8?x?abc?= and =?broken");
+    }
+
+}
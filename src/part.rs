@@ -1,11 +1,19 @@
 
 
-use crate::{EmailBody,ContentEncoding,Part,ContentDecoded};
+use crate::{EmailBody,ContentEncoding,Part,ContentDecoded,ContentDisposition,DispositionKind,PartRef};
 use base64::decode as Base64Decode;
 use quoted_printable::decode as QPDecode;
 use quoted_printable::ParseMode as QpParseMode;
+use crate::parser::decode_text;
+use crate::decode::base64_lenient;
+use crate::sniff::sniff_part;
+use crate::digest::Digest;
 
-pub fn init(email:&mut EmailBody)->Result<(),&'static str>{
+///decodes and classifies the collected parts, in lenient mode a part that fails keeps its error and the rest go on.
+#[allow(clippy::len_zero,clippy::needless_return)]
+pub fn init(email:&mut EmailBody,lenient:bool)->Result<Vec<PartRef>,&'static str>{
+
+    let mut refs:Vec<PartRef> = vec![];
 
     loop{
 
@@ -15,50 +23,37 @@ pub fn init(email:&mut EmailBody)->Result<(),&'static str>{
 
         let part = email.parts.remove(0);
         
-        match parse_part(part,email){
-            Ok(_)=>{},
+        let mut fallback = part.clone();
+        match parse_part(part,email,lenient){
+            Ok(r)=>{refs.push(r);},
             Err(_e)=>{
-                println!("!!! failed-parse_part : {}",_e);
-                return Err("failed-parse-part");
+                if !lenient{
+                    println!("!!! failed-parse_part : {}",_e);
+                    return Err("failed-parse-part");
+                }
+                fallback.error = Some(_e);
+                email.warnings.push(_e);
+                refs.push(place(fallback,email));
             }
         }
 
     }
 
-    return Ok(());
+    return Ok(refs);
 
 }
 
-fn parse_part(mut part:Part,email:&mut EmailBody)->Result<(),&'static str>{
+#[allow(clippy::len_zero)]
+fn parse_part(mut part:Part,email:&mut EmailBody,lenient:bool)->Result<PartRef,&'static str>{
+
+    if let Some(v) = part.feature("Content-Disposition"){part.disposition = ContentDisposition::parse(v);}
 
     let encoding:ContentEncoding;
-    match part.content_features.get("Content-Transfer-Encoding"){
-        Some(v)=>{
-            let v = v.to_lowercase();
-            if v.contains("base64"){encoding = ContentEncoding::Base64;} else 
-            if v.contains("quoted-printable"){encoding = ContentEncoding::Qp;} else
-            if v.contains("qp"){encoding = ContentEncoding::Qp;} else
-            if v.contains("text"){encoding = ContentEncoding::String;} else
-            if v.contains("string"){encoding = ContentEncoding::String;} else
-            if v.contains("7bit"){encoding = ContentEncoding::String;} else
-            if v.contains("8bit"){encoding = ContentEncoding::String;} else
-            if v.contains("binary"){encoding = ContentEncoding::UnSupported;} 
-            else {encoding = ContentEncoding::String;}
-        },
+    match part.feature("Content-Transfer-Encoding"){
+        Some(v)=>{encoding = content_encoding(v);},
         None=>{
-            match email.headers.get("Content-Transfer-Encoding"){
-                Some(v)=>{
-                    let v = v.to_lowercase();
-                    if v.contains("base64"){encoding = ContentEncoding::Base64;} else 
-                    if v.contains("quoted-printable"){encoding = ContentEncoding::Qp;} else
-                    if v.contains("qp"){encoding = ContentEncoding::Qp;} else
-                    if v.contains("text"){encoding = ContentEncoding::String;} else
-                    if v.contains("string"){encoding = ContentEncoding::String;} else
-                    if v.contains("7bit"){encoding = ContentEncoding::String;} else
-                    if v.contains("8bit"){encoding = ContentEncoding::String;} else
-                    if v.contains("binary"){encoding = ContentEncoding::UnSupported;} 
-                    else {encoding = ContentEncoding::String;}
-                },
+            match email.headers.get("content-transfer-encoding"){
+                Some(v)=>{encoding = content_encoding(v);},
                 None=>{
                     encoding = ContentEncoding::String;
                 }
@@ -66,107 +61,96 @@ fn parse_part(mut part:Part,email:&mut EmailBody)->Result<(),&'static str>{
         }
     }
 
-    
-    let mut is_string = false;
-    if part.content_type.0.len() == 0{
-        if email.content_type.0.len() > 0{
-            if 
-                email.content_type.0.contains("html") || 
-                email.content_type.0.contains("text") || 
-                email.content_type.0.contains("string") || 
-                email.content_type.0.contains("utf-8")
-            {
-                is_string = true;
-            }
-        }
-    } else {
-        if 
-            part.content_type.0.contains("html") || 
-            part.content_type.0.contains("text") || 
-            part.content_type.0.contains("string") || 
-            part.content_type.0.contains("utf-8")
-        {
-            is_string = true;
-        }
+    //parts written to an attachment sink only keep their metadata
+    if part.written.is_some(){
+        part.data = Vec::new();
+        return Ok(place(part,email));
     }
 
+    //only text is decoded to a string, a part without a type has the type of a single part email and
+    //text/plain otherwise (rfc 2045)
+    let content_type:String = if !part.content_type.0.is_empty(){
+        part.content_type.0.to_lowercase()
+    } else if !email.content_type.0.is_empty() && !email.content_type.0.starts_with("multipart/"){
+        email.content_type.0.to_lowercase()
+    } else {
+        "text/plain".to_string()
+    };
+    let is_string = content_type.starts_with("text/");
+
     // println!("encoding : {:?} is_string : {:?} {:?}",encoding,is_string,part.content_type);
 
+    let charset:Option<String> = if part.content_type.0.len() == 0{
+        email.content_type.1.get("charset").cloned()
+    } else {
+        part.content_type.1.get("charset").cloned()
+    };
+
     
-    let decoded:ContentDecoded;
+    //the body without its transfer encoding, 7bit, 8bit and binary bodies are taken as they are
+    let bytes:Vec<u8>;
     match encoding{
         ContentEncoding::Base64=>{
-            while part.data.contains("\r\n"){
-                part.data = part.data.replace("\r\n","");
-            }
-            while part.data.contains("\n"){
-                part.data = part.data.replace("\n","");
-            }
-            // println!("\n\n{:?}\n\n",part.data);
-            match Base64Decode(part.data.clone()){
-                Ok(v)=>{
-                    if is_string{
-                        match String::from_utf8(v){
-                            Ok(v)=>{
-                                // println!("{:?}",v);
-                                decoded = ContentDecoded::String(v);
-                            },
-                            Err(_)=>{
-                                return Err("failed-parse_base64_to_string");
-                            }
-                        }
-                    } else {
-                        decoded = ContentDecoded::Base64(v);
-                    }
-                },
+            let cleaned:Vec<u8> = part.data.iter().filter(|b|!b.is_ascii_whitespace()).copied().collect();
+            match Base64Decode(&cleaned){
+                Ok(v)=>{bytes = v;},
                 Err(_)=>{
-                    return Err("failed-decode-base64");
+                    if !lenient{
+                        return Err("failed-decode-base64");
+                    }
+                    part.error = Some("failed-decode-base64");
+                    bytes = base64_lenient(&cleaned);
                 }
             }
         },
         ContentEncoding::Qp=>{
-            match QPDecode(part.data.clone(),QpParseMode::Strict){
-                Ok(v)=>{
-                    if is_string{
-                        match String::from_utf8(v){
-                            Ok(v)=>{
-                                decoded = ContentDecoded::String(v);
-                            },
-                            Err(_)=>{
-                                return Err("failed-parse_qp_to_string");
-                            }
-                        }
-                    } else {
-                        decoded = ContentDecoded::Qp(v);
-                    }
-                },
+            match QPDecode(&part.data,QpParseMode::Strict){
+                Ok(v)=>{bytes = v;},
                 Err(_)=>{
-                    return Err("failed-decode-base64");
+                    if !lenient{
+                        return Err("failed-decode-qp");
+                    }
+                    part.error = Some("failed-decode-qp");
+                    match QPDecode(&part.data,QpParseMode::Robust){
+                        Ok(v)=>{bytes = v;},
+                        Err(_)=>{return Err("failed-decode-qp");}
+                    }
                 }
             }
         },
-        ContentEncoding::String=>{
-            decoded = ContentDecoded::String(part.data.clone());
-        }
+        ContentEncoding::String|ContentEncoding::EightBit|ContentEncoding::Binary=>{
+            bytes = std::mem::take(&mut part.data);
+        },
         ContentEncoding::UnSupported=>{
             return Err("unsupported-content-encoding");
         }
     }
 
+    //the detected type is only reported, the declared type decides how the body is decoded
+    sniff_part(&mut part,&bytes);
+    part.digest = Digest::of(&bytes).ok();
+
+    let decoded:ContentDecoded;
+    if is_string{
+        let (text,lossy) = decode_text(charset.as_deref(),&bytes);
+        part.lossy = lossy;
+        decoded = ContentDecoded::String(text);
+    } else {
+        decoded = match encoding{
+            ContentEncoding::Base64=>ContentDecoded::Base64(bytes),
+            ContentEncoding::Qp=>ContentDecoded::Qp(bytes),
+            _=>ContentDecoded::Binary(bytes)
+        };
+    }
+
     match decoded{
         ContentDecoded::String(v)=>{
-            if part.content_type.0.len() == 0{
-                if email.content_type.0.contains("html"){
-                    part.decoded = ContentDecoded::Html(v);
-                } else {
-                    part.decoded = ContentDecoded::String(v);
-                }
+            //text is handed out with lf line endings whatever the input used
+            let v = if v.contains('\r'){v.replace("\r\n","\n")} else {v};
+            if content_type == "text/html"{
+                part.decoded = ContentDecoded::Html(v);
             } else {
-                if part.content_type.0.contains("html"){
-                    part.decoded = ContentDecoded::Html(v);
-                } else {
-                    part.decoded = ContentDecoded::String(v);
-                }
+                part.decoded = ContentDecoded::String(v);
             }
         },
         _=>{
@@ -174,21 +158,94 @@ fn parse_part(mut part:Part,email:&mut EmailBody)->Result<(),&'static str>{
         }
     }
 
-    part.data = String::new();
+    part.data = Vec::new();
 
-    match part.content_features.get("Content-Disposition"){
-        Some(v)=>{
-            if v.contains("attachment"){
-                email.attachments.push(part);
-            } else {
-                email.body.push(part);
-            }
-        },
-        None=>{
-            email.body.push(part);
-        }
+    if let Some(e) = part.error{email.warnings.push(e);}
+
+    Ok(place(part,email))
+
+}
+
+fn place(part:Part,email:&mut EmailBody)->PartRef{
+    if is_attachment(&part,email){
+        email.attachments.push(part);
+        PartRef::Attachment(email.attachments.len() - 1)
+    } else {
+        email.body.push(part);
+        PartRef::Body(email.body.len() - 1)
     }
+}
+
+///maps a Content-Transfer-Encoding value to the decoding it needs.
+pub fn content_encoding(value:&str)->ContentEncoding{
+    let v = value.to_lowercase();
+    if v.contains("base64"){return ContentEncoding::Base64;}
+    if v.contains("quoted-printable"){return ContentEncoding::Qp;}
+    if v.contains("qp"){return ContentEncoding::Qp;}
+    if v.contains("binary"){return ContentEncoding::Binary;}
+    if v.contains("8bit"){return ContentEncoding::EightBit;}
+    ContentEncoding::String
+}
 
-    return Ok(());
+///attachment parts are explicit attachments, inline parts carrying a filename that are not text and
+///undisposed parts that either carry a filename or are not text.
+fn is_attachment(part:&Part,email:&EmailBody)->bool{
 
-}
\ No newline at end of file
+    let content_type:&str = if part.content_type.0.is_empty(){
+        &email.content_type.0
+    } else {
+        &part.content_type.0
+    };
+    let is_text = 
+        content_type.is_empty() || 
+        content_type.starts_with("text/") || 
+        content_type.starts_with("multipart/");
+    let has_filename = part.filename().is_some();
+
+    match &part.disposition.kind{
+        DispositionKind::Attachment=>{true},
+        DispositionKind::Inline=>{has_filename && !is_text},
+        _=>{has_filename || !is_text}
+    }
+
+}
+#[cfg(test)]
+mod tests{
+
+    use super::content_encoding;
+    use crate::{Config,parse_bytes,DispositionKind,ContentDecoded,ContentEncoding};
+
+    const MIXED:&str = "Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nbody\r\n--b\r\nContent-Type: text/plain\r\nContent-Disposition: inline; filename=notes.txt\r\n\r\nnotes\r\n--b\r\nContent-Type: image/png\r\nContent-Disposition: inline; filename=\"logo.png\"\r\nContent-Transfer-Encoding: base64\r\n\r\naGk=\r\n--b\r\nContent-Type: application/pdf\r\nContent-Transfer-Encoding: base64\r\n\r\naGk=\r\n--b\r\nContent-Type: text/csv\r\nContent-Disposition: ATTACHMENT; filename=a.csv; size=5\r\n\r\na,b\r\n--b--\r\n";
+
+    #[test]
+    fn classification(){
+        let email = parse_bytes(MIXED.as_bytes(),&Config::new().unwrap()).unwrap();
+        let body:Vec<&str> = email.body.iter().map(|p|p.content_type.0.as_str()).collect();
+        let attachments:Vec<&str> = email.attachments.iter().map(|p|p.content_type.0.as_str()).collect();
+        assert_eq!(body,vec!["text/plain","text/plain"]);
+        assert_eq!(attachments,vec!["image/png","application/pdf","text/csv"]);
+        let csv = &email.attachments[2];
+        assert_eq!(csv.disposition.kind,DispositionKind::Attachment);
+        assert_eq!(csv.disposition.size,Some(5));
+        assert_eq!(csv.filename().map(|v|v.as_str()),Some("a.csv"));
+        assert_eq!(email.attachments[1].disposition.kind,DispositionKind::None);
+    }
+
+
+    #[test]
+    fn binary_encodings(){
+        assert!(matches!(content_encoding("Binary"),ContentEncoding::Binary));
+        assert!(matches!(content_encoding(" 8BIT "),ContentEncoding::EightBit));
+        assert!(matches!(content_encoding("7bit"),ContentEncoding::String));
+        let mut raw:Vec<u8> = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\nna\xc3\xafve \xe2\x9c\x93\r\n--b\r\nContent-Type: image/png\r\nContent-Transfer-Encoding: 8bit\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[137,80,78,71,0,255]);
+        raw.extend_from_slice(b"\r\n--b\r\nContent-Type: application/octet-stream\r\nContent-Transfer-Encoding: binary\r\n\r\n\x00--b\xff\r\n--b--\r\n");
+        let email = parse_bytes(&raw,&Config::new().unwrap()).unwrap();
+        let text = &email.body[0];
+        assert!(matches!(&text.decoded,ContentDecoded::String(v) if v == "na\u{ef}ve \u{2713}"));
+        assert!(!text.lossy);
+        assert!(matches!(&email.attachments[0].decoded,ContentDecoded::Binary(v) if v == &[137,80,78,71,0,255]));
+        assert!(matches!(&email.attachments[1].decoded,ContentDecoded::Binary(v) if v == b"\x00--b\xff"));
+    }
+
+}
@@ -0,0 +1,554 @@
+use crate::config::{Config,EmailBody,Part,ContentEncoding,ContentDisposition};
+use crate::stream::{StreamParser,Event};
+use crate::parser::Lines;
+use crate::decode::StreamDecoder;
+use crate::mime::PartRef;
+use crate::part::content_encoding;
+use crate::sniff::{sniff_part,SNIFF_WINDOW};
+use crate::digest::{Digest,DigestHasher};
+use tokio::io::{AsyncRead,AsyncReadExt,AsyncWrite,AsyncWriteExt};
+use std::collections::HashMap;
+use tokio_util::codec::Decoder;
+use bytes::BytesMut;
+
+const READ_SIZE:usize = 8192;
+
+///parses a raw message from a file, socket or any other reader until it is exhausted.
+///
+///the data is fed to a `StreamParser` as it is read so the whole message is never buffered as one input,
+///with `Config::dot_stuffed` the reader is read as smtp DATA through `DataCodec` and must contain the end flag.
+///
+/// ```
+/// use letterman_email_body_parser::{parse_reader,Config};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let conf = Config::new().unwrap();
+///     let file = tokio::fs::File::open("./sldv_atch.txt").await.unwrap();
+///     let email = parse_reader(file,&conf).await.unwrap();
+///     println!("{:?}",email.headers.get("subject"));
+/// }
+/// ```
+pub async fn parse_reader(reader:impl AsyncRead + Unpin,config:&Config)->Result<EmailBody,&'static str>{
+
+    let mut reader = reader;
+
+    if config.dot_stuffed{
+        let mut codec = DataCodec::new(config);
+        let mut src = BytesMut::with_capacity(READ_SIZE);
+        loop{
+            let read:usize = match reader.read_buf(&mut src).await{
+                Ok(v)=>{v},
+                Err(_)=>{
+                    return Err("failed-read-reader");
+                }
+            };
+            let decoded = if read == 0{
+                codec.decode_eof(&mut src)
+            } else {
+                codec.decode(&mut src)
+            };
+            match decoded{
+                Ok(Some(v))=>{return Ok(v);},
+                Ok(None)=>{
+                    if read == 0{
+                        return Err("not_found-data-end_flag");
+                    }
+                },
+                Err(e)=>{
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof{
+                        return Err("not_found-data-end_flag");
+                    }
+                    return Err("failed-parse-data");
+                }
+            }
+        }
+    }
+
+    let mut stream = StreamParser::new(config);
+    stream.quiet();
+    let mut buffer = vec![0;READ_SIZE];
+
+    loop{
+        let read:usize = match reader.read(&mut buffer).await{
+            Ok(v)=>{v},
+            Err(_)=>{
+                return Err("failed-read-reader");
+            }
+        };
+        if read == 0{
+            break;
+        }
+        stream.feed(&buffer[..read])?;
+    }
+
+    stream.finish()
+
+}
+
+///parses a raw message from a reader and decodes the parts picked by the sink straight into its writers.
+///
+///the sink is asked for every leaf part once its headers are read, parts it returns a writer for are base64 or
+///quoted-printable decoded chunk by chunk into that writer and keep only their metadata, `Part::written` holds
+///the number of decoded bytes. parts without a writer are kept in memory as usual. with `Config::dot_stuffed`
+///the reader is unstuffed as smtp DATA and must contain the end flag, like in `parse_reader`. with `Config::lenient`
+///bodies that fail strict decoding are decoded the same lenient way as in memory and keep their `Part::error`.
+///
+/// ```
+/// use letterman_email_body_parser::{parse_reader_sink,Config};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let conf = Config::new().unwrap();
+///     let file = tokio::fs::File::open("./sldv_atch.txt").await.unwrap();
+///     let email = parse_reader_sink(file,&conf,|part|{
+///         if part.filename().is_some(){
+///             return Some(tokio::io::sink());
+///         }
+///         return None;
+///     }).await.unwrap();
+///     for part in email.attachments.iter(){
+///         println!("{:?} {:?}",part.filename(),part.written);
+///     }
+/// }
+/// ```
+pub async fn parse_reader_sink<R,W,F>(reader:R,config:&Config,sink:F)->Result<EmailBody,&'static str>
+where
+    R:AsyncRead + Unpin,
+    W:AsyncWrite + Unpin,
+    F:FnMut(&Part)->Option<W>
+{
+
+    let mut reader = reader;
+    let mut sink = sink;
+    let mut stream = StreamParser::new(config);
+    let mut writers:HashMap<usize,(W,StreamDecoder,DigestHasher,Vec<u8>)> = HashMap::new();
+    let mut written:Vec<(usize,Digest,Vec<u8>,Option<&'static str>)> = vec![];
+    let mut buffer = vec![0;READ_SIZE];
+    let mut unstuffer = if config.dot_stuffed{Some(Unstuffer::new())} else {None};
+
+    loop{
+        let read:usize = match reader.read(&mut buffer).await{
+            Ok(v)=>{v},
+            Err(_)=>{
+                return Err("failed-read-reader");
+            }
+        };
+        if read == 0{
+            break;
+        }
+        let events = match &mut unstuffer{
+            Some(v)=>{
+                let data = v.push(&buffer[..read]);
+                stream.feed(&data)?
+            },
+            None=>{stream.feed(&buffer[..read])?}
+        };
+        sink_events(events,Some(&mut stream),config.lenient,&mut sink,&mut writers,&mut written).await?;
+        if unstuffer.as_ref().map_or(false,|v|v.ended){
+            break;
+        }
+    }
+
+    if unstuffer.as_ref().map_or(false,|v|!v.ended){
+        return Err("not_found-data-end_flag");
+    }
+
+    let (mut email,events) = stream.finish_events()?;
+    sink_events(events,None,config.lenient,&mut sink,&mut writers,&mut written).await?;
+
+    for (index,digest,head,error) in written{
+        let part_ref = email.tree.find_leaf(index).and_then(|n|n.part);
+        let part = match part_ref{
+            Some(PartRef::Body(i))=>{email.body.get_mut(i)},
+            Some(PartRef::Attachment(i))=>{email.attachments.get_mut(i)},
+            None=>{None}
+        };
+        if let Some(v) = part{
+            v.written = Some(digest.size);
+            v.error = error;
+            if let Some(e) = error{email.warnings.push(e);}
+            v.digest = Some(digest);
+            sniff_part(v,&head);
+        }
+    }
+
+    Ok(email)
+
+}
+
+async fn sink_events<W,F>(
+    events:Vec<Event>,
+    stream:Option<&mut StreamParser<'_>>,
+    lenient:bool,
+    sink:&mut F,
+    writers:&mut HashMap<usize,(W,StreamDecoder,DigestHasher,Vec<u8>)>,
+    written:&mut Vec<(usize,Digest,Vec<u8>,Option<&'static str>)>
+)->Result<(),&'static str>
+where
+    W:AsyncWrite + Unpin,
+    F:FnMut(&Part)->Option<W>
+{
+    let mut stream = stream;
+    for event in events{
+        match event{
+            Event::PartStart(index,mut part)=>{
+                if let Some(v) = part.feature("Content-Disposition"){part.disposition = ContentDisposition::parse(v);}
+                if let Some(writer) = sink(&part){
+                    let encoding = match part.feature("Content-Transfer-Encoding"){
+                        Some(v)=>{content_encoding(v)},
+                        None=>{ContentEncoding::String}
+                    };
+                    if let Some(v) = &mut stream{v.release(index);}
+                    writers.insert(index,(writer,StreamDecoder::new(&encoding,lenient),DigestHasher::new()?,Vec::new()));
+                }
+            },
+            Event::PartData(index,data)=>{
+                if let Some((writer,decoder,hasher,head)) = writers.get_mut(&index){
+                    let decoded = decoder.push(&data)?;
+                    //the start of the body is kept to detect its type
+                    let room = SNIFF_WINDOW.saturating_sub(head.len()).min(decoded.len());
+                    head.extend_from_slice(&decoded[..room]);
+                    if writer.write_all(&decoded).await.is_err(){
+                        return Err("failed-write-sink");
+                    }
+                    hasher.update(&decoded)?;
+                }
+            },
+            Event::PartEnd(index)=>{
+                if let Some((mut writer,mut decoder,mut hasher,mut head)) = writers.remove(&index){
+                    let decoded = decoder.finish()?;
+                    let room = SNIFF_WINDOW.saturating_sub(head.len()).min(decoded.len());
+                    head.extend_from_slice(&decoded[..room]);
+                    if writer.write_all(&decoded).await.is_err(){
+                        return Err("failed-write-sink");
+                    }
+                    if writer.shutdown().await.is_err(){
+                        return Err("failed-write-sink");
+                    }
+                    hasher.update(&decoded)?;
+                    written.push((index,hasher.finish()?,head,decoder.error));
+                }
+            },
+            _=>{}
+        }
+    }
+    Ok(())
+}
+
+///decodes the DATA section of an smtp session into emails.
+///
+///leading dots are unstuffed and the "\r\n.\r\n" end flag completes the message, the line ending in front of
+///the end flag belongs to it and is not part of the email. use with `tokio_util::codec::FramedRead`.
+pub struct DataCodec<'a>{
+    config:&'a Config,
+    stream:Option<StreamParser<'a>>,
+    unstuffer:Unstuffer
+}
+
+impl<'a> DataCodec<'a>{
+    pub fn new(config:&'a Config)->DataCodec<'a>{
+        DataCodec{
+            config,
+            stream:None,
+            unstuffer:Unstuffer::new()
+        }
+    }
+}
+
+impl<'a> Decoder for DataCodec<'a>{
+    type Item = EmailBody;
+    type Error = std::io::Error;
+    fn decode(&mut self,src:&mut BytesMut)->Result<Option<EmailBody>,std::io::Error>{
+        loop{
+
+            let index:usize = match src.iter().position(|b|*b == b'\n'){
+                Some(v)=>{v},
+                None=>{return Ok(None);}
+            };
+            let data = src.split_to(index + 1);
+            let mut line:&[u8] = &data[..index];
+            let mut ending:&[u8] = &data[index..];
+            if line.ends_with(b"\r"){
+                ending = &data[index-1..];
+                line = &line[..line.len()-1];
+            }
+
+            let config = self.config;
+            let stream = self.stream.get_or_insert_with(||{
+                let mut stream = StreamParser::new(config);
+                stream.quiet();
+                stream
+            });
+
+            let chunk = match self.unstuffer.line(line,ending){
+                Some(v)=>{v},
+                None=>{
+                    //end flag
+                    self.unstuffer = Unstuffer::new();
+                    match self.stream.take(){
+                        Some(v)=>{
+                            match v.finish(){
+                                Ok(email)=>{return Ok(Some(email));},
+                                Err(e)=>{return Err(invalid(e));}
+                            }
+                        },
+                        None=>{return Ok(None);}
+                    }
+                }
+            };
+            match stream.feed(&chunk){
+                Ok(_)=>{},
+                Err(e)=>{
+                    self.stream = None;
+                    self.unstuffer = Unstuffer::new();
+                    return Err(invalid(e));
+                }
+            }
+
+        }
+    }
+    fn decode_eof(&mut self,src:&mut BytesMut)->Result<Option<EmailBody>,std::io::Error>{
+        if let Some(v) = self.decode(src)?{return Ok(Some(v));}
+        if src.is_empty() && self.stream.is_none(){
+            return Ok(None);
+        }
+        Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,"not_found-data-end_flag"))
+    }
+}
+
+//unstuffs DATA line by line, the line ending of a line is held back until the next one as the ending in front
+//of the end flag is not part of the email
+struct Unstuffer{
+    carry:Vec<u8>,
+    pending:Vec<u8>,
+    ended:bool
+}
+
+impl Unstuffer{
+    fn new()->Unstuffer{
+        Unstuffer{
+            carry:Vec::new(),
+            pending:Vec::new(),
+            ended:false
+        }
+    }
+    //the bytes to parse for a line, None once it is the end flag
+    fn line(&mut self,line:&[u8],ending:&[u8])->Option<Vec<u8>>{
+        let line = unstuff_line(line)?;
+        let mut chunk = std::mem::replace(&mut self.pending,ending.to_vec());
+        chunk.extend_from_slice(line);
+        Some(chunk)
+    }
+    //unstuffs the complete lines of the data, anything after the end flag is ignored
+    fn push(&mut self,data:&[u8])->Vec<u8>{
+        let mut collect:Vec<u8> = Vec::new();
+        if self.ended{
+            return collect;
+        }
+        //only the new data can hold a line break
+        let searched = self.carry.len();
+        self.carry.extend_from_slice(data);
+        let end = match self.carry[searched..].iter().rposition(|b|*b == b'\n'){
+            Some(v)=>{searched + v + 1},
+            None=>{return collect;}
+        };
+        let lines:Vec<u8> = self.carry.drain(..end).collect();
+        for (line,ending,_) in Lines::new(&lines){
+            //the data ends with a line ending, the empty rest after it is not a line
+            if ending.is_empty(){
+                break;
+            }
+            match self.line(line,ending){
+                Some(v)=>{collect.extend_from_slice(&v);},
+                None=>{
+                    self.ended = true;
+                    self.carry.clear();
+                    return collect;
+                }
+            }
+        }
+        collect
+    }
+}
+
+///strips the "." end line of smtp DATA and unstuffs leading dots (rfc 5321 4.5.2).
+///
+///the line ending in front of the end line belongs to it and is dropped, anything after it is ignored,
+///input without an end line is unstuffed as a whole.
+///
+/// ```
+/// use letterman_email_body_parser::unstuff;
+///
+/// assert_eq!(unstuff(b"a\r\n..b\r\n.\r\n"),b"a\r\n.b".to_vec());
+/// ```
+pub fn unstuff(input:&[u8])->Vec<u8>{
+    let mut collect:Vec<u8> = Vec::with_capacity(input.len());
+    let mut pending:&[u8] = b"";
+    for (line,ending,_) in Lines::new(input){
+        match unstuff_line(line){
+            Some(v)=>{
+                collect.extend_from_slice(pending);
+                collect.extend_from_slice(v);
+                pending = ending;
+            },
+            None=>{
+                return collect;
+            }
+        }
+    }
+    collect.extend_from_slice(pending);
+    collect
+}
+
+///a DATA line without its line ending, returns None for the end line and the line without the stuffed dot otherwise.
+pub fn unstuff_line(line:&[u8])->Option<&[u8]>{
+    if line == b"."{
+        return None;
+    }
+    if line.starts_with(b"."){
+        return Some(&line[1..]);
+    }
+    Some(line)
+}
+
+fn invalid(e:&'static str)->std::io::Error{
+    std::io::Error::new(std::io::ErrorKind::InvalidData,e)
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{parse_reader,parse_reader_sink,DataCodec,Unstuffer,unstuff};
+    use crate::{Config,parse_bytes,ContentDecoded};
+    use tokio_util::codec::Decoder;
+    use bytes::BytesMut;
+
+    const DATA:&str = "Subject: one\r\n\r\n..dotted\r\nline\r\n.\r\nSubject: two\r\n\r\nsecond\r\n.\r\n";
+
+    fn text(decoded:&ContentDecoded)->&str{
+        match decoded{
+            ContentDecoded::String(v)=>{v},
+            _=>{""}
+        }
+    }
+
+    #[tokio::test]
+    async fn reader_matches_bytes(){
+        let config = Config::new().unwrap();
+        let input = crate::io::read_file("./sldv_atch.txt").unwrap();
+        let file = tokio::fs::File::open("./sldv_atch.txt").await.unwrap();
+        let email = parse_reader(file,&config).await.unwrap();
+        let memory = parse_bytes(&input,&config).unwrap();
+        assert_eq!(email.header_list,memory.header_list);
+        assert_eq!(email.attachments.len(),memory.attachments.len());
+        assert_eq!(email.attachment_summary(),memory.attachment_summary());
+    }
+
+    #[test]
+    fn codec_splits_messages(){
+        let config = Config::new().unwrap();
+        let mut codec = DataCodec::new(&config);
+        let mut src = BytesMut::new();
+        let mut emails = vec![];
+        //fed a byte at a time like a slow client
+        for b in DATA.as_bytes(){
+            src.extend_from_slice(&[*b]);
+            if let Some(v) = codec.decode(&mut src).unwrap(){emails.push(v);}
+        }
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+        assert_eq!(emails.len(),2);
+        assert_eq!(text(&emails[0].body[0].decoded),".dotted\nline");
+        assert_eq!(emails[1].headers.get("subject").map(|v|v.as_str()),Some("two"));
+    }
+
+    #[test]
+    fn codec_needs_end_flag(){
+        let config = Config::new().unwrap();
+        let mut codec = DataCodec::new(&config);
+        let mut src = BytesMut::from("Subject: one\r\n\r\nbody\r\n");
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert_eq!(codec.decode_eof(&mut src).unwrap_err().kind(),std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn reader_dot_stuffed(){
+        let mut config = Config::new().unwrap();
+        config.dot_stuffed = true;
+        let email = parse_reader(DATA.as_bytes(),&config).await.unwrap();
+        assert_eq!(email.headers.get("subject").map(|v|v.as_str()),Some("one"));
+        assert_eq!(parse_reader(&b"Subject: one\r\n\r\nbody\r\n"[..],&config).await.err(),Some("not_found-data-end_flag"));
+    }
+
+    #[test]
+    fn unstuffing(){
+        assert_eq!(unstuff(b"a\r\n..b\r\n.c\r\n.\r\nignored\r\n"),b"a\r\n.b\r\nc".to_vec());
+        assert_eq!(unstuff(b"a\n..\n"),b"a\n.\n".to_vec());
+        let mut config = Config::new().unwrap();
+        config.dot_stuffed = true;
+        let email = parse_bytes(DATA.as_bytes(),&config).unwrap();
+        assert_eq!(text(&email.body[0].decoded),".dotted\nline");
+    }
+
+    #[test]
+    fn unstuff_across_chunks(){
+        //every split point, including inside the crlf and the end flag
+        let data = b"a\r\n..b\r\n.\r\n.\r\nafter";
+        for split in 0..data.len(){
+            let mut unstuffer = Unstuffer::new();
+            let mut collect = unstuffer.push(&data[..split]);
+            collect.extend(unstuffer.push(&data[split..]));
+            assert_eq!(collect,unstuff(data),"split at {}",split);
+            assert!(unstuffer.ended);
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_dot_stuffed_in_small_reads(){
+        let mut config = Config::new().unwrap();
+        config.dot_stuffed = true;
+        let data = "Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\n..hi\r\n--b\r\nContent-Type: application/pdf\r\nContent-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQK\r\n--b--\r\n.\r\n";
+        //a pipe that hands out a byte per read
+        let (mut client,server) = tokio::io::duplex(1);
+        tokio::spawn(async move{
+            tokio::io::AsyncWriteExt::write_all(&mut client,data.as_bytes()).await.unwrap();
+        });
+        let email = parse_reader_sink(server,&config,|part|{
+            if part.content_type.0 == "application/pdf"{
+                return Some(tokio::io::sink());
+            }
+            None
+        }).await.unwrap();
+        assert_eq!(text(&email.body[0].decoded),".hi");
+        assert_eq!(email.attachments[0].written,Some(9));
+        assert_eq!(email.attachment_summary(),parse_bytes(data.as_bytes(),&config).unwrap().attachment_summary());
+    }
+
+
+    //the same malformed bodies through a sink and in memory, in lenient mode they must decode alike
+    #[tokio::test]
+    async fn sink_lenient_parity(){
+        let raw = "Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nhi\r\n--b\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=a.bin\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\nok=3D=\r\nbad=ZZ=4\r\nafter=41\r\n--b\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=b.bin\r\nContent-Transfer-Encoding: base64\r\n\r\nQUJD\r\nRE*VG\r\nSEk=QQ\r\n--b--\r\n";
+        let mut config = Config::new().unwrap();
+        assert!(parse_reader_sink(raw.as_bytes(),&config,|_|Some(tokio::io::sink())).await.is_err());
+        config.lenient = true;
+        let memory = parse_bytes(raw.as_bytes(),&config).unwrap();
+        for size in [1,5,4096]{
+            let (mut client,server) = tokio::io::duplex(size);
+            tokio::spawn(async move{
+                tokio::io::AsyncWriteExt::write_all(&mut client,raw.as_bytes()).await.unwrap();
+            });
+            let email = parse_reader_sink(server,&config,|part|{
+                if part.filename().is_some(){
+                    return Some(tokio::io::sink());
+                }
+                None
+            }).await.unwrap();
+            assert_eq!(email.attachment_summary(),memory.attachment_summary());
+            let errors:Vec<Option<&str>> = email.attachments.iter().map(|p|p.error).collect();
+            assert_eq!(errors,vec![Some("failed-decode-qp"),Some("failed-decode-base64")]);
+            assert_eq!(errors,memory.attachments.iter().map(|p|p.error).collect::<Vec<Option<&str>>>());
+        }
+    }
+
+}
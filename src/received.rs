@@ -0,0 +1,288 @@
+use crate::date::EmailDate;
+use std::net::IpAddr;
+
+///allowed clock difference in seconds between relays before a hop is considered out of order.
+const CLOCK_SKEW:i64 = 300;
+
+///a single relay hop parsed from a Received header.
+#[derive(Debug,Clone)]
+pub struct Hop{
+    pub raw:String,
+    pub from:Option<String>,
+    pub from_host:Option<String>,
+    pub ips:Vec<IpAddr>,
+    pub by:Option<String>,
+    pub via:Option<String>,
+    pub with:Option<String>,
+    pub id:Option<String>,
+    pub recipient:Option<String>,
+    pub date:Option<EmailDate>,
+    ///seconds spent since the previous (older) hop.
+    pub delay:Option<i64>,
+    ///the connecting ip is private, loopback or link local.
+    pub private:bool,
+    ///the hop is inconsistent with the rest of the chain, see `hop_chain`.
+    pub forged:bool
+}
+
+impl Hop{
+    pub fn new(raw:&str)->Hop{
+        Hop{
+            raw:raw.to_string(),
+            from:None,
+            from_host:None,
+            ips:Vec::new(),
+            by:None,
+            via:None,
+            with:None,
+            id:None,
+            recipient:None,
+            date:None,
+            delay:None,
+            private:false,
+            forged:false
+        }
+    }
+}
+
+///parses the value of a single Received header (rfc 5321 4.4).
+pub fn parse_received(value:&str)->Hop{
+
+    let mut hop = Hop::new(value);
+
+    let clauses:&str;
+    match find_date_separator(value){
+        Some(index)=>{
+            clauses = &value[..index];
+            hop.date = EmailDate::parse(&value[index+1..]).ok();
+        },
+        None=>{
+            clauses = value;
+        }
+    }
+
+    let mut keyword = String::new();
+    let mut from_comments:Vec<String> = vec![];
+    for token in tokenize(clauses){
+        if token.starts_with('('){
+            if keyword == "from"{
+                from_comments.push(token[1..token.len()-1].to_string());
+            }
+            continue;
+        }
+        let lower = token.to_lowercase();
+        match lower.as_str(){
+            "from"|"by"|"via"|"with"|"id"|"for"=>{
+                keyword = lower;
+                continue;
+            },
+            _=>{}
+        }
+        let slot = match keyword.as_str(){
+            "from"=>&mut hop.from,
+            "by"=>&mut hop.by,
+            "via"=>&mut hop.via,
+            "with"=>&mut hop.with,
+            "id"=>&mut hop.id,
+            "for"=>&mut hop.recipient,
+            _=>{continue;}
+        };
+        if slot.is_none(){
+            *slot = Some(token.trim_start_matches('<').trim_end_matches('>').to_string());
+        }
+    }
+
+    if let Some(v) = &hop.from{collect_ips(v,&mut hop.ips);}
+    for comment in from_comments.iter(){
+        collect_ips(comment,&mut hop.ips);
+        if hop.from_host.is_none(){
+            for word in comment.split_whitespace(){
+                let word = word.trim_end_matches('.');
+                if word.contains('.') && !word.starts_with('[') && word.parse::<IpAddr>().is_err(){
+                    hop.from_host = Some(word.to_string());
+                    break;
+                }
+            }
+        }
+    }
+
+    hop.private = hop.ips.iter().any(is_private);
+
+    hop
+
+}
+
+///builds the delivery path from Received headers given in message order (newest first).
+///
+///hops are returned oldest first with the delay from the previous hop, a hop is flagged as forged when it
+///has no by clause, is dated in the future or claims to be later than the hop that received it.
+pub fn hop_chain(headers:Vec<&String>)->Vec<Hop>{
+
+    let mut chain:Vec<Hop> = headers.iter().rev().map(|v|parse_received(v)).collect();
+
+    let mut previous:Option<EmailDate> = None;
+    for hop in chain.iter_mut(){
+        if let (Some(p),Some(d)) = (previous,hop.date){hop.delay = Some(p.seconds_until(d.timestamp));}
+        if hop.date.is_some(){
+            previous = hop.date;
+        }
+        if hop.by.is_none(){
+            hop.forged = true;
+        }
+        if hop.date.map_or(false,|d|d.is_future(CLOCK_SKEW)){
+            hop.forged = true;
+        }
+    }
+
+    for index in 1..chain.len(){
+        if let (Some(older),Some(newer)) = (chain[index-1].date,chain[index].date){
+            if older.timestamp > newer.timestamp + CLOCK_SKEW{
+                chain[index-1].forged = true;
+            }
+        }
+    }
+
+    chain
+
+}
+
+///private, loopback, link local, shared (cgnat) and unspecified addresses.
+pub fn is_private(ip:&IpAddr)->bool{
+    match ip{
+        IpAddr::V4(v)=>{
+            let octets = v.octets();
+            v.is_private() || v.is_loopback() || v.is_link_local() || v.is_unspecified() ||
+                (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+        },
+        IpAddr::V6(v)=>{
+            let first = v.segments()[0];
+            v.is_loopback() || v.is_unspecified() ||
+                (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+//the date follows the last semicolon that is not inside a comment
+fn find_date_separator(value:&str)->Option<usize>{
+    let mut found:Option<usize> = None;
+    let mut depth:usize = 0;
+    for (index,c) in value.char_indices(){
+        match c{
+            '('=>{depth += 1;},
+            ')'=>{depth = depth.saturating_sub(1);},
+            ';' if depth == 0=>{found = Some(index);},
+            _=>{}
+        }
+    }
+    found
+}
+
+//splits clauses into words and whole comments, comments keep their parentheses
+fn tokenize(value:&str)->Vec<String>{
+    let mut collect:Vec<String> = vec![];
+    let mut current = String::new();
+    let mut depth:usize = 0;
+    for c in value.chars(){
+        if depth > 0{
+            current.push(c);
+            if c == '('{depth += 1;} else if c == ')'{
+                depth -= 1;
+                if depth == 0{
+                    collect.push(current);
+                    current = String::new();
+                }
+            }
+        } else if c == '('{
+            if !current.is_empty(){
+                collect.push(current);
+                current = String::new();
+            }
+            current.push(c);
+            depth = 1;
+        } else if c.is_whitespace(){
+            if !current.is_empty(){
+                collect.push(current);
+                current = String::new();
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty(){
+        if depth > 0{
+            current.push(')');
+        }
+        collect.push(current);
+    }
+    collect
+}
+
+fn collect_ips(value:&str,ips:&mut Vec<IpAddr>){
+    let words = value.split(|c:char|c.is_whitespace() || c == '[' || c == ']' || c == '(' || c == ')' || c == ',');
+    for word in words{
+        let word = word.trim_start_matches("IPv6:").trim_start_matches("ipv6:");
+        if let Ok(ip) = word.parse::<IpAddr>(){
+            if !ips.contains(&ip){
+                ips.push(ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{parse_received,is_private};
+    use crate::{Config,parse_bytes};
+    use std::net::IpAddr;
+
+    const RAW:&str = "Received: from mx.example.org (mx.example.org [203.0.113.5])\r\n\tby mail.example.com (Postfix) with ESMTPS id 4AbC\r\n\tfor <user@example.com>; Mon, 1 Jan 2001 10:05:00 +0000\r\nReceived: from laptop (unknown [192.168.1.20])\r\n by mx.example.org with ESMTPSA id X1; Mon, 1 Jan 2001 10:00:00 +0000\r\nSubject: hi\r\n\r\nbody\r\n";
+
+    #[test]
+    fn clauses(){
+        let hop = parse_received("from relay (relay.example.net [IPv6:2001:db8::1]) by mx.example.com via smtp with ESMTP id abc (queue 1) for <a@b.example>; Mon, 1 Jan 2001 10:00:00 +0100 (CET)");
+        assert_eq!(hop.from.as_deref(),Some("relay"));
+        assert_eq!(hop.from_host.as_deref(),Some("relay.example.net"));
+        assert_eq!(hop.ips,vec!["2001:db8::1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(hop.by.as_deref(),Some("mx.example.com"));
+        assert_eq!(hop.via.as_deref(),Some("smtp"));
+        assert_eq!(hop.with.as_deref(),Some("ESMTP"));
+        assert_eq!(hop.id.as_deref(),Some("abc"));
+        assert_eq!(hop.recipient.as_deref(),Some("a@b.example"));
+        assert_eq!(hop.date.unwrap().offset,3600);
+        assert!(!hop.private);
+    }
+
+    #[test]
+    fn private_ranges(){
+        assert!(is_private(&"10.1.2.3".parse().unwrap()));
+        assert!(is_private(&"100.64.0.1".parse().unwrap()));
+        assert!(is_private(&"fe80::1".parse().unwrap()));
+        assert!(!is_private(&"100.128.0.1".parse().unwrap()));
+        assert!(!is_private(&"2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn chain(){
+        let email = parse_bytes(RAW.as_bytes(),&Config::new().unwrap()).unwrap();
+        let hops = &email.received;
+        assert_eq!(hops.len(),2);
+        assert_eq!(hops[0].from.as_deref(),Some("laptop"));
+        assert!(hops[0].private);
+        assert_eq!(hops[0].delay,None);
+        assert_eq!(hops[1].by.as_deref(),Some("mail.example.com"));
+        assert_eq!(hops[1].recipient.as_deref(),Some("user@example.com"));
+        assert_eq!(hops[1].delay,Some(300));
+        assert!(!hops[0].forged && !hops[1].forged);
+    }
+
+    #[test]
+    fn forged_hops(){
+        let raw = "Received: from a by b; Mon, 1 Jan 2001 10:00:00 +0000\r\nReceived: from c by d; Mon, 1 Jan 2001 11:00:00 +0000\r\nReceived: from e; Mon, 1 Jan 2001 09:00:00 +0000\r\n\r\nbody\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        let forged:Vec<bool> = email.received.iter().map(|h|h.forged).collect();
+        //no by clause, then a hop dated after the one that received it
+        assert_eq!(forged,vec![true,true,false]);
+    }
+
+}
@@ -0,0 +1,491 @@
+use crate::html::{tokenize,find_ignore_case,Token};
+use crate::config::normalize_cid;
+use std::collections::HashMap;
+
+//removed together with everything inside them
+const DROPPED:[&str;14] = [
+    "script","iframe","frame","frameset","object","embed","applet","template","svg","math",
+    "noscript","select","textarea","button"
+];
+//removed, their content is kept
+const UNWRAPPED:[&str;3] = ["form","fieldset","label"];
+//elements without content that are removed
+const VOID_DROPPED:[&str;7] = ["link","meta","base","input","param","source","track"];
+//elements without content or close tag
+const VOID:[&str;14] = ["area","base","br","col","embed","hr","img","input","link","meta","param","source","track","wbr"];
+const URL_ATTRIBUTES:[&str;9] = ["href","src","action","formaction","background","poster","cite","longdesc","xlink:href"];
+
+///what the sanitizer may keep.
+#[derive(Debug,Clone)]
+pub struct SanitizeOptions{
+    ///keep images and backgrounds loaded from http(s), they are blocked by default as they reveal when the email is read.
+    pub allow_remote_images:bool,
+    ///urls to use for `cid:` references, keyed by Content-ID without angle brackets.
+    pub cid_urls:HashMap<String,String>
+}
+
+impl SanitizeOptions{
+    pub fn new()->SanitizeOptions{
+        SanitizeOptions{
+            allow_remote_images:false,
+            cid_urls:HashMap::new()
+        }
+    }
+}
+
+impl Default for SanitizeOptions{
+    fn default()->SanitizeOptions{
+        SanitizeOptions::new()
+    }
+}
+
+///everything the sanitizer removed or changed.
+#[derive(Debug,Clone,Default)]
+pub struct SanitizeReport{
+    pub removed_elements:Vec<String>,
+    pub removed_attributes:Vec<String>,
+    ///urls with a dangerous scheme, remote resources that were not allowed and unresolved cid references.
+    pub blocked_urls:Vec<String>,
+    pub rewritten_cids:Vec<String>
+}
+
+impl SanitizeReport{
+    pub fn is_clean(&self)->bool{
+        self.removed_elements.is_empty() && self.removed_attributes.is_empty() &&
+            self.blocked_urls.is_empty()
+    }
+}
+
+///makes html safe to display, scripts, event handlers, forms, dangerous urls and remote resources are removed.
+///
+///links keep http, https, mailto and tel targets, images keep data:image urls and `cid:` references are
+///rewritten with `SanitizeOptions::cid_urls`, styles that load anything are dropped unless remote images are allowed.
+///
+/// ```
+/// use letterman_email_body_parser::sanitize::{sanitize,SanitizeOptions};
+///
+/// let mut options = SanitizeOptions::new();
+/// options.cid_urls.insert("logo@x".to_string(),"/inline/1".to_string());
+/// let (html,report) = sanitize("<p onclick=\"x()\">hi<script>x()</script><img src=\"cid:logo@x\"></p>",&options);
+/// assert_eq!(html,"<p>hi<img src=\"/inline/1\"></p>");
+/// assert_eq!(report.removed_elements,vec!["script"]);
+/// ```
+pub fn sanitize(html:&str,options:&SanitizeOptions)->(String,SanitizeReport){
+
+    let mut report = SanitizeReport::default();
+    let mut out = String::with_capacity(html.len());
+    let mut dropping:Option<(String,usize)> = None;
+    let mut style:Option<String> = None;
+
+    for token in tokenize(html){
+
+        //inside a removed element only its nesting is tracked
+        if let Some((name,depth)) = &mut dropping{
+            match &token{
+                Token::Open(n,_,self_closing) if n == name && !*self_closing=>{*depth += 1;},
+                Token::Close(n) if n == name=>{
+                    *depth -= 1;
+                    if *depth == 0{
+                        dropping = None;
+                    }
+                },
+                _=>{}
+            }
+            continue;
+        }
+
+        match token{
+            Token::Text(text)=>{
+                match &mut style{
+                    Some(v)=>{v.push_str(&text);},
+                    None=>{
+                        if text == "<"{
+                            out.push_str("&lt;");
+                        } else {
+                            out.push_str(&text.replace('<',"&lt;").replace('>',"&gt;"));
+                        }
+                    }
+                }
+            },
+            Token::Comment(_)=>{},
+            Token::Open(name,attrs,self_closing)=>{
+                if DROPPED.contains(&name.as_str()){
+                    report.removed_elements.push(name.clone());
+                    if !self_closing && !VOID.contains(&name.as_str()){
+                        dropping = Some((name,1));
+                    }
+                    continue;
+                }
+                if VOID_DROPPED.contains(&name.as_str()) || UNWRAPPED.contains(&name.as_str()){
+                    report.removed_elements.push(name);
+                    continue;
+                }
+                if name == "style"{
+                    style = Some(String::new());
+                    continue;
+                }
+                out.push('<');
+                out.push_str(&name);
+                for (key,value) in attrs{
+                    if let Some(v) = clean_attribute(&name,&key,&value,options,&mut report){
+                        out.push(' ');
+                        out.push_str(&key);
+                        out.push_str("=\"");
+                        out.push_str(&escape(&v));
+                        out.push('"');
+                    }
+                }
+                out.push('>');
+            },
+            Token::Close(name)=>{
+                if DROPPED.contains(&name.as_str()) || VOID_DROPPED.contains(&name.as_str()) || UNWRAPPED.contains(&name.as_str()){
+                    continue;
+                }
+                if name == "style"{
+                    if let Some(css) = style.take(){
+                        if loads_resources(&css,options.allow_remote_images){
+                            report.removed_elements.push(name);
+                        } else {
+                            out.push_str("<style>");
+                            out.push_str(&css.replace('<',""));
+                            out.push_str("</style>");
+                        }
+                    }
+                    continue;
+                }
+                if VOID.contains(&name.as_str()){
+                    continue;
+                }
+                out.push_str("</");
+                out.push_str(&name);
+                out.push('>');
+            }
+        }
+
+    }
+
+    //an unclosed style sheet is dropped
+    if style.is_some(){report.removed_elements.push("style".to_string());}
+
+    (out,report)
+
+}
+
+//the value to keep for an attribute or None to drop it
+fn clean_attribute(element:&str,key:&str,value:&str,options:&SanitizeOptions,report:&mut SanitizeReport)->Option<String>{
+
+    if key.starts_with("on") || key == "formaction" || key == "srcdoc" || key == "ping" ||
+        !key.chars().all(|c|c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ':'){
+        report.removed_attributes.push(key.to_string());
+        return None;
+    }
+
+    if key == "style"{
+        if loads_resources(value,options.allow_remote_images){
+            report.removed_attributes.push(key.to_string());
+            return None;
+        }
+        return Some(value.to_string());
+    }
+
+    //srcset can not be rewritten url by url here, it is only kept for local images
+    if key == "srcset"{
+        if options.allow_remote_images{
+            return Some(value.to_string());
+        }
+        report.removed_attributes.push(key.to_string());
+        return None;
+    }
+
+    if !URL_ATTRIBUTES.contains(&key){
+        return Some(value.to_string());
+    }
+
+    let url:String = value.chars().filter(|c|!c.is_whitespace() && !c.is_control()).collect();
+    //browsers read backslashes in urls as slashes, "\\host" and "/\host" are protocol relative
+    let lower = url.to_lowercase().replace('\\',"/");
+    let loads = !(element == "a" || element == "area") || key != "href";
+
+    if let Some(id) = cid_reference(&url){
+        match options.cid_urls.get(&id){
+            Some(v)=>{
+                report.rewritten_cids.push(id);
+                return Some(v.clone());
+            },
+            None=>{
+                report.blocked_urls.push(value.to_string());
+                return None;
+            }
+        }
+    }
+
+    let scheme = match lower.find(':'){
+        Some(i) if !lower[..i].contains('/') && !lower[..i].contains('?') && !lower[..i].contains('#')=>{&lower[..i]},
+        _=>{""}
+    };
+
+    let allowed = if loads{
+        match scheme{
+            "http"|"https"=>{options.allow_remote_images},
+            "data"=>{element == "img" && lower.starts_with("data:image/") && !lower.starts_with("data:image/svg")},
+            ""=>{!lower.starts_with("//") || options.allow_remote_images},
+            _=>{false}
+        }
+    } else {
+        matches!(scheme,"http"|"https"|"mailto"|"tel"|"")
+    };
+
+    if !allowed{
+        report.blocked_urls.push(value.to_string());
+        return None;
+    }
+    Some(url)
+
+}
+
+///replaces the `cid:` references of html with the urls `resolve` returns for their Content-ID.
+///
+///only url attributes like src, href and background and css url() in style attributes and style sheets are
+///rewritten, references that do not resolve and everything else are kept. the html is written back from its
+///tokens so attribute quoting can change, a leading doctype is kept.
+///
+/// ```
+/// use letterman_email_body_parser::sanitize::rewrite_cids;
+///
+/// let html = rewrite_cids("<p title='cid:a@b'><img src=\"cid:a@b\"> cid:a@b</p>",|id|Some(format!("/inline/{}",id)));
+/// assert_eq!(html,"<p title=\"cid:a@b\"><img src=\"/inline/a@b\"> cid:a@b</p>");
+/// ```
+pub fn rewrite_cids<F>(html:&str,mut resolve:F)->String
+where
+    F:FnMut(&str)->Option<String>
+{
+
+    let mut out = String::with_capacity(html.len());
+    let mut style = false;
+
+    let start = html.trim_start();
+    if start.get(..9).map_or(false,|v|v.eq_ignore_ascii_case("<!doctype")){
+        if let Some(end) = start.find('>'){out.push_str(&start[..end + 1]);}
+    }
+
+    for token in tokenize(html){
+        match token{
+            Token::Text(text)=>{
+                if style{
+                    out.push_str(&rewrite_css(&text,&mut resolve));
+                } else {
+                    out.push_str(&text);
+                }
+            },
+            Token::Comment(text)=>{
+                out.push_str("<!--");
+                out.push_str(&text);
+                out.push_str("-->");
+            },
+            Token::Open(name,attrs,self_closing)=>{
+                out.push('<');
+                out.push_str(&name);
+                for (key,value) in attrs{
+                    let value = if key == "style"{
+                        rewrite_css(&value,&mut resolve)
+                    } else if URL_ATTRIBUTES.contains(&key.as_str()){
+                        match cid_reference(&value).and_then(|id|resolve(&id)){
+                            Some(v)=>{v},
+                            None=>{value}
+                        }
+                    } else {
+                        value
+                    };
+                    out.push(' ');
+                    out.push_str(&key);
+                    out.push_str("=\"");
+                    out.push_str(&escape(&value));
+                    out.push('"');
+                }
+                if self_closing{
+                    out.push_str(" /");
+                }
+                out.push('>');
+                style = name == "style" && !self_closing;
+            },
+            Token::Close(name)=>{
+                style = false;
+                out.push_str("</");
+                out.push_str(&name);
+                out.push('>');
+            }
+        }
+    }
+
+    out
+
+}
+
+//rewrites the cid references in the url() functions of css
+fn rewrite_css<F>(css:&str,resolve:&mut F)->String
+where
+    F:FnMut(&str)->Option<String>
+{
+    let mut collect = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = find_ignore_case(rest,"url("){
+        let open = start + 4;
+        let url_start = open + (rest[open..].len() - rest[open..].trim_start().len());
+        let (url_start,url_end) = match rest[url_start..].chars().next(){
+            Some(q) if q == '"' || q == '\''=>{
+                let end = rest[url_start + 1..].find(q).map_or(rest.len(),|v|url_start + 1 + v);
+                (url_start + 1,end)
+            },
+            _=>{
+                let end = rest[url_start..].find(|c:char|c == ')' || c.is_whitespace()).map_or(rest.len(),|v|url_start + v);
+                (url_start,end)
+            }
+        };
+        collect.push_str(&rest[..url_start]);
+        let url = &rest[url_start..url_end];
+        match cid_reference(url).and_then(|id|resolve(&id)){
+            Some(v)=>{collect.push_str(&v);},
+            None=>{collect.push_str(url);}
+        }
+        rest = &rest[url_end..];
+    }
+    collect.push_str(rest);
+    collect
+}
+
+//the normalized Content-ID a cid: url points at
+fn cid_reference(url:&str)->Option<String>{
+    let url = url.trim();
+    if url.get(..4).map_or(false,|v|v.eq_ignore_ascii_case("cid:")){
+        return Some(normalize_cid(url));
+    }
+    None
+}
+
+//css that can fetch something or run code, urls are fine when remote images are allowed
+fn loads_resources(css:&str,allow_remote:bool)->bool{
+    let lower:String = unescape_css(css).to_lowercase().chars().filter(|c|!c.is_whitespace()).collect();
+    let fetches = lower.contains("url(") || lower.contains("image-set(") || lower.contains("image(") || lower.contains("src(");
+    (fetches && !allow_remote) || lower.contains("@import") || lower.contains("expression(") ||
+        lower.contains("javascript:") || lower.contains("behavior:") || lower.contains("-moz-binding")
+}
+
+//resolves css escapes ("\75 rl" is "url") and drops comments so they can not hide a function name
+fn unescape_css(css:&str)->String{
+    let mut collect = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    while let Some(c) = chars.next(){
+        if c == '/' && chars.peek() == Some(&'*'){
+            chars.next();
+            let mut last = ' ';
+            for c in chars.by_ref(){
+                if last == '*' && c == '/'{
+                    break;
+                }
+                last = c;
+            }
+            continue;
+        }
+        if c != '\\'{
+            collect.push(c);
+            continue;
+        }
+        let mut hex = String::new();
+        while hex.len() < 6{
+            match chars.peek(){
+                Some(h) if h.is_ascii_hexdigit()=>{
+                    hex.push(*h);
+                    chars.next();
+                },
+                _=>{break;}
+            }
+        }
+        if hex.is_empty(){
+            //an escaped line break is removed, any other character stands for itself
+            match chars.next(){
+                Some('\n')=>{},
+                Some(v)=>{collect.push(v);},
+                None=>{}
+            }
+            continue;
+        }
+        //one whitespace ends a hex escape and belongs to it
+        match chars.peek(){
+            Some(w) if w.is_whitespace()=>{chars.next();},
+            _=>{}
+        }
+        let code = u32::from_str_radix(&hex,16).unwrap_or(0xfffd);
+        collect.push(std::char::from_u32(code).filter(|v|*v != '\0').unwrap_or('\u{fffd}'));
+    }
+    collect
+}
+
+fn escape(value:&str)->String{
+    value.replace('&',"&amp;").replace('"',"&quot;").replace('<',"&lt;").replace('>',"&gt;")
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{sanitize,SanitizeOptions};
+
+    fn clean(html:&str)->String{
+        sanitize(html,&SanitizeOptions::new()).0
+    }
+
+    #[test]
+    fn scripts_and_handlers(){
+        let (html,report) = sanitize("<div onclick=\"x()\" title=\"t\">a<object>b<object>c</object>d</object>e<script>x()</script></div>",&SanitizeOptions::new());
+        assert_eq!(html,"<div title=\"t\">ae</div>");
+        assert_eq!(report.removed_attributes,vec!["onclick"]);
+        assert_eq!(report.removed_elements,vec!["object","script"]);
+        assert_eq!(clean("<form action=\"/x\"><input name=q>text</form>"),"text");
+        assert_eq!(clean("<iframe src=\"x\"></iframe><p>1 < 2</p>"),"<p>1 &lt; 2</p>");
+    }
+
+    #[test]
+    fn link_schemes(){
+        assert_eq!(clean("<a href=\"javascript:alert(1)\">a</a>"),"<a>a</a>");
+        assert_eq!(clean("<a href=\" jav&#x09;ascript:alert(1)\">a</a>"),"<a>a</a>");
+        assert_eq!(clean("<a href=\"https://x.com/?a=1&amp;b=2\">a</a>"),"<a href=\"https://x.com/?a=1&amp;b=2\">a</a>");
+        assert_eq!(clean("<a href=\"mailto:a@b.c\">a</a>"),"<a href=\"mailto:a@b.c\">a</a>");
+    }
+
+    #[test]
+    fn remote_images(){
+        assert_eq!(clean("<img src=\"http://t/p.gif\">"),"<img>");
+        assert_eq!(clean("<img src=\"//t/p.gif\">"),"<img>");
+        assert_eq!(clean("<img src=\"\\\\evil.com/p.gif\">"),"<img>");
+        assert_eq!(clean("<img src=\"/\\evil.com/p.gif\">"),"<img>");
+        assert_eq!(clean("<img src=\"data:image/png;base64,AAA\">"),"<img src=\"data:image/png;base64,AAA\">");
+        assert_eq!(clean("<img src=\"data:image/svg+xml,x\">"),"<img>");
+        assert_eq!(clean("<img src=\"images/a.png\">"),"<img src=\"images/a.png\">");
+        let mut options = SanitizeOptions::new();
+        options.allow_remote_images = true;
+        assert_eq!(sanitize("<img src=\"http://t/p.gif\">",&options).0,"<img src=\"http://t/p.gif\">");
+    }
+
+    #[test]
+    fn styles(){
+        assert_eq!(clean("<p style=\"color:red\">a</p>"),"<p style=\"color:red\">a</p>");
+        assert_eq!(clean("<p style=\"background:url(http://e/x)\">a</p>"),"<p>a</p>");
+        assert_eq!(clean("<p style=\"background-image:image-set('http://e/x' 1x)\">a</p>"),"<p>a</p>");
+        assert_eq!(clean("<p style=\"background-image:-webkit-image-set('http://e/x' 1x)\">a</p>"),"<p>a</p>");
+        assert_eq!(clean("<p style=\"background:\\75 rl(http://e/y)\">a</p>"),"<p>a</p>");
+        assert_eq!(clean("<p style=\"background:u\\rl(http://e/y)\">a</p>"),"<p>a</p>");
+        assert_eq!(clean("<style>p{background:\\000075rl(http://e/y)}</style>"),"");
+        assert_eq!(clean("<style>@im/**/port 'http://e';</style>"),"");
+        assert_eq!(clean("<style>p{color:red}</style>"),"<style>p{color:red}</style>");
+    }
+
+    #[test]
+    fn cid_references(){
+        let mut options = SanitizeOptions::new();
+        options.cid_urls.insert("logo@x".to_string(),"/inline/1".to_string());
+        let (html,report) = sanitize("<img src=\"cid:logo%40x\"><img src=\"CID:<logo@x>\"><img src=\"cid:none\">",&options);
+        assert_eq!(html,"<img src=\"/inline/1\"><img src=\"/inline/1\"><img>");
+        assert_eq!(report.rewritten_cids,vec!["logo@x","logo@x"]);
+        assert_eq!(report.blocked_urls,vec!["cid:none"]);
+    }
+
+}
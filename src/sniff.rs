@@ -0,0 +1,253 @@
+use crate::Part;
+
+///how many leading bytes of a body are enough to detect its type, zip entries are looked for in this window too.
+pub const SNIFF_WINDOW:usize = 4096;
+
+//leading bytes and the type they mark
+const SIGNATURES:[(&[u8],&str);22] = [
+    (b"%PDF-","application/pdf"),
+    (b"\x89PNG\r\n\x1a\n","image/png"),
+    (b"\xff\xd8\xff","image/jpeg"),
+    (b"GIF87a","image/gif"),
+    (b"GIF89a","image/gif"),
+    (b"II*\x00","image/tiff"),
+    (b"MM\x00*","image/tiff"),
+    (b"\x00\x00\x01\x00","image/x-icon"),
+    (b"\x1f\x8b","application/gzip"),
+    (b"BZh","application/x-bzip2"),
+    (b"\xfd7zXZ\x00","application/x-xz"),
+    (b"Rar!\x1a\x07","application/vnd.rar"),
+    (b"7z\xbc\xaf\x27\x1c","application/x-7z-compressed"),
+    (b"\x7fELF","application/x-executable"),
+    (b"\xcf\xfa\xed\xfe","application/x-mach-binary"),
+    (b"\xce\xfa\xed\xfe","application/x-mach-binary"),
+    (b"\xca\xfe\xba\xbe","application/java-vm"),
+    (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1","application/x-ole-storage"),
+    (b"ID3","audio/mpeg"),
+    (b"OggS","audio/ogg"),
+    (b"fLaC","audio/flac"),
+    (b"\x1a\x45\xdf\xa3","video/webm")
+];
+
+//declared types a detected container type can stand for
+const FAMILIES:[(&str,&[&str]);4] = [
+    ("application/zip",&["application/x-zip-compressed","application/x-zip","application/java-archive","application/epub+zip"]),
+    ("application/x-ole-storage",&["application/msword","application/vnd.ms-excel","application/vnd.ms-powerpoint","application/vnd.ms-outlook","application/x-msi"]),
+    ("image/jpeg",&["image/jpg","image/pjpeg"]),
+    ("application/pdf",&["application/x-pdf","application/acrobat"])
+];
+
+///the type of a body detected from its magic bytes.
+///
+///some signatures are plain ascii ("%PDF-", "ID3", "OggS") so text starting with them matches too, the result
+///is a hint to compare with the declared type and never decides how a part is decoded.
+///
+///zip archives are told apart by their entries into office open xml and opendocument types, windows
+///executables need their pe header.
+///
+/// ```
+/// use letterman_email_body_parser::sniff::sniff;
+///
+/// assert_eq!(sniff(b"%PDF-1.7\n..."),Some("application/pdf"));
+/// assert_eq!(sniff(b"hello world"),None);
+/// ```
+pub fn sniff(bytes:&[u8])->Option<&'static str>{
+
+    for (magic,kind) in SIGNATURES.iter(){
+        if bytes.starts_with(magic){
+            return Some(kind);
+        }
+    }
+
+    if bytes.starts_with(b"PK\x03\x04"){
+        return Some(sniff_zip(&bytes[..bytes.len().min(SNIFF_WINDOW)]));
+    }
+
+    if bytes.len() >= 12 && bytes.starts_with(b"RIFF"){
+        match &bytes[8..12]{
+            b"WEBP"=>{return Some("image/webp");},
+            b"WAVE"=>{return Some("audio/wav");},
+            b"AVI "=>{return Some("video/x-msvideo");},
+            _=>{}
+        }
+    }
+
+    //iso media files name their format in the major brand, unknown brands are not guessed
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp"{
+        match &bytes[8..12]{
+            b"heic"|b"heix"|b"mif1"=>{return Some("image/heic");},
+            b"avif"|b"avis"=>{return Some("image/avif");},
+            b"M4A "=>{return Some("audio/mp4");},
+            b"qt  "=>{return Some("video/quicktime");},
+            b"isom"|b"iso2"|b"iso4"|b"iso5"|b"iso6"|b"mp41"|b"mp42"|b"avc1"|b"M4V "|b"dash"=>{return Some("video/mp4");},
+            brand if brand.starts_with(b"3gp")=>{return Some("video/3gpp");},
+            _=>{return None;}
+        }
+    }
+
+    //"MZ" is common in text, the pe header it points at is required
+    if bytes.len() >= 64 && bytes.starts_with(b"MZ"){
+        let offset = u32::from_le_bytes([bytes[60],bytes[61],bytes[62],bytes[63]]) as usize;
+        if bytes.len() >= offset + 4 && &bytes[offset..offset + 4] == b"PE\x00\x00"{
+            return Some("application/x-msdownload");
+        }
+    }
+
+    //the reserved bytes of a bitmap header are zero
+    if bytes.len() >= 14 && bytes.starts_with(b"BM") && bytes[6..10] == [0,0,0,0]{
+        return Some("image/bmp");
+    }
+
+    None
+
+}
+
+//office documents are zip archives, their first entries name the application
+fn sniff_zip(bytes:&[u8])->&'static str{
+    if bytes.len() > 38 && &bytes[30..38] == b"mimetype"{
+        let rest = &bytes[38..];
+        if rest.starts_with(b"application/vnd.oasis.opendocument.text"){
+            return "application/vnd.oasis.opendocument.text";
+        }
+        if rest.starts_with(b"application/vnd.oasis.opendocument.spreadsheet"){
+            return "application/vnd.oasis.opendocument.spreadsheet";
+        }
+        if rest.starts_with(b"application/vnd.oasis.opendocument.presentation"){
+            return "application/vnd.oasis.opendocument.presentation";
+        }
+        if rest.starts_with(b"application/epub+zip"){
+            return "application/epub+zip";
+        }
+    }
+    let contains = |needle:&[u8]|bytes.windows(needle.len()).any(|w|w == needle);
+    if contains(b"word/"){
+        return "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+    }
+    if contains(b"xl/"){
+        return "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+    }
+    if contains(b"ppt/"){
+        return "application/vnd.openxmlformats-officedocument.presentationml.presentation";
+    }
+    "application/zip"
+}
+
+///true when the declared content type can carry the detected one.
+///
+///aliases like image/jpg and the documents stored in zip and ole containers count as the same type,
+///application/octet-stream does not as it hides the real type.
+pub fn compatible(declared:&str,sniffed:&str)->bool{
+    let declared = declared.trim().to_lowercase();
+    if declared == sniffed{
+        return true;
+    }
+    for (kind,aliases) in FAMILIES.iter(){
+        if sniffed == *kind && aliases.contains(&declared.as_str()){
+            return true;
+        }
+    }
+    //documents stored in a zip are often sent as a plain zip
+    if is_zip(&declared) && (sniffed.contains("openxmlformats") || sniffed.contains("opendocument") || sniffed == "application/epub+zip"){
+        return true;
+    }
+    //a zip detected where only its entries could tell the exact office type
+    if sniffed == "application/zip"{
+        return
+            declared.starts_with("application/vnd.openxmlformats-officedocument.") ||
+            declared.starts_with("application/vnd.oasis.opendocument.") ||
+            declared.starts_with("application/vnd.ms-") && declared.ends_with(".macroenabled.12");
+    }
+    if sniffed == "application/x-msdownload"{
+        return matches!(declared.as_str(),"application/x-msdos-program"|"application/x-dosexec"|"application/vnd.microsoft.portable-executable");
+    }
+    if sniffed == "audio/wav"{
+        return matches!(declared.as_str(),"audio/x-wav"|"audio/wave"|"audio/vnd.wave");
+    }
+    false
+}
+
+fn is_zip(declared:&str)->bool{
+    declared == "application/zip" || FAMILIES[0].1[..2].contains(&declared)
+}
+
+//records the detected type of a decoded body, a part without a content type is text/plain
+pub(crate) fn sniff_part(part:&mut Part,bytes:&[u8]){
+    part.sniffed_type = sniff(bytes);
+    part.type_mismatch = match part.sniffed_type{
+        Some(sniffed)=>{
+            let declared = if part.content_type.0.is_empty(){"text/plain"} else {part.content_type.0.as_str()};
+            !compatible(declared,sniffed)
+        },
+        None=>{false}
+    };
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{sniff,compatible};
+    use crate::{Config,parse_bytes,ContentDecoded};
+
+    #[test]
+    fn signatures(){
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."),Some("image/png"));
+        assert_eq!(sniff(b"MZ not an executable at all, just some text starting with the letters mz ......"),None);
+        assert_eq!(sniff(b"BM is a band"),None);
+        assert_eq!(sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "),Some("image/webp"));
+        assert_eq!(sniff(b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00"),Some("image/avif"));
+        assert_eq!(sniff(b"\x00\x00\x00\x14ftyp3gp5\x00\x00\x00\x00"),Some("video/3gpp"));
+        assert_eq!(sniff(b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00"),Some("video/mp4"));
+        assert_eq!(sniff(b"\x00\x00\x00\x18ftypcrx \x00\x00\x00\x01"),None);
+    }
+
+    #[test]
+    fn compatibility(){
+        assert!(compatible("image/jpg","image/jpeg"));
+        assert!(compatible("application/zip","application/vnd.openxmlformats-officedocument.wordprocessingml.document"));
+        assert!(compatible("application/msword","application/x-ole-storage"));
+        assert!(!compatible("application/octet-stream","image/png"));
+        assert!(!compatible("application/pdf","application/x-msdownload"));
+    }
+
+    #[test]
+    fn text_stays_text(){
+        //"ID3 tags are broken" in base64
+        let raw = "Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\nSUQzIHRhZ3MgYXJlIGJyb2tlbg==\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        let part = &email.body[0];
+        assert_eq!(part.sniffed_type,Some("audio/mpeg"));
+        assert!(part.type_mismatch);
+        assert!(matches!(&part.decoded,ContentDecoded::String(v) if v == "ID3 tags are broken"));
+        assert_eq!(email.text_body().unwrap(),"ID3 tags are broken");
+    }
+
+    #[test]
+    fn binary_text_subtype(){
+        //an opendocument text is a zip, "text" in its subtype does not make it text
+        let mut odt:Vec<u8> = b"PK\x03\x04".to_vec();
+        odt.extend_from_slice(&[0;26]);
+        odt.extend_from_slice(b"mimetypeapplication/vnd.oasis.opendocument.text\xff\x00\x9f\r\n");
+        let raw = format!(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\n\r\nno type\r\n--b\r\nContent-Type: application/vnd.oasis.opendocument.text\r\nContent-Disposition: attachment; filename=a.odt\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n--b\r\nContent-Type: application/json\r\nContent-Disposition: attachment; filename=a.json\r\n\r\n{{}}\r\n--b--\r\n",
+            base64::encode(&odt)
+        );
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        assert!(matches!(&email.body[0].decoded,ContentDecoded::String(v) if v == "no type"));
+        let part = &email.attachments[0];
+        assert!(matches!(&part.decoded,ContentDecoded::Base64(v) if v == &odt));
+        assert_eq!(part.sniffed_type,Some("application/vnd.oasis.opendocument.text"));
+        assert!(!part.type_mismatch);
+        assert!(matches!(&email.attachments[1].decoded,ContentDecoded::Binary(v) if v == b"{}"));
+    }
+
+    #[test]
+    fn mislabeled_attachment(){
+        let raw = "Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nhi\r\n--b\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=a.pdf\r\nContent-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQK\r\n--b--\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        let part = &email.attachments[0];
+        assert_eq!(part.sniffed_type,Some("application/pdf"));
+        assert!(part.type_mismatch);
+        assert!(matches!(&part.decoded,ContentDecoded::Base64(v) if v.starts_with(b"%PDF-")));
+    }
+
+}
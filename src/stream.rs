@@ -0,0 +1,608 @@
+use crate::config::{Config,PartHandler,EmailBody,Part,LineEnding};
+use crate::mime::MimeNode;
+use crate::parser;
+
+///what the stream parser found in the data fed so far.
+#[derive(Debug,Clone)]
+pub enum Event{
+    ///a top level header, folded lines are already unfolded.
+    Header(String,String),
+    ///the headers of a leaf part are complete, the index is the position of the leaf among all leaves.
+    PartStart(usize,Box<Part>),
+    ///undecoded body bytes of a leaf part, the line ending before the next boundary is left out.
+    PartData(usize,Vec<u8>),
+    PartEnd(usize)
+}
+
+///push parser for messages that arrive in chunks, like smtp DATA.
+///
+///bytes are fed as they are received and only an unfinished header or delimiter line is buffered between calls, events report
+///headers and part data as soon as a line is complete, `finish` decodes the parts and returns the email.
+///with `retain(false)` part bodies are only handed out as events so memory stays bounded for large messages.
+///
+/// ```
+/// use letterman_email_body_parser::{Config,StreamParser,Event,io};
+///
+/// let input = io::read_file("./sldv_atch.txt").unwrap();
+/// let conf = Config::new().unwrap();
+/// let mut stream = StreamParser::new(&conf);
+/// for chunk in input.chunks(64){
+///     for event in stream.feed(chunk).unwrap(){
+///         match event{
+///             Event::PartStart(index,part)=>{println!("part {} : {}",index,part.content_type.0);},
+///             _=>{}
+///         }
+///     }
+/// }
+/// let email = stream.finish().unwrap();
+/// println!("{:?}",email.headers.get("subject"));
+/// ```
+pub struct StreamParser<'a>{
+    config:&'a Config,
+    depth:usize,
+    emit:bool,
+    events:Vec<Event>,
+    error:Option<&'static str>,
+    buffer:Vec<u8>,
+    //bytes at the start of the buffer already searched for a line break
+    scanned:usize,
+    //the start of the current line was handed out as body data, the rest of it is data too
+    continued:bool,
+    position:usize,
+    held:Option<Vec<u8>>,
+    in_headers:bool,
+    in_part:bool,
+    leaf_active:bool,
+    leaf_pending:Vec<u8>,
+    frames:Vec<Frame>,
+    part_handler:PartHandler,
+    body:EmailBody,
+    root:Option<MimeNode>,
+    crlf_count:usize,
+    lf_count:usize
+}
+
+impl<'a> StreamParser<'a>{
+    pub fn new(config:&'a Config)->StreamParser<'a>{
+        StreamParser{
+            config,
+            depth:0,
+            emit:true,
+            events:Vec::new(),
+            error:None,
+            buffer:Vec::new(),
+            scanned:0,
+            continued:false,
+            position:0,
+            held:None,
+            in_headers:true,
+            in_part:false,
+            leaf_active:false,
+            leaf_pending:Vec::new(),
+            frames:Vec::new(),
+            part_handler:PartHandler::new(),
+            body:EmailBody::new(),
+            root:None,
+            crlf_count:0,
+            lf_count:0
+        }
+    }
+    //parser for a whole input held in memory, no events are collected
+    pub(crate) fn nested(config:&'a Config,depth:usize)->StreamParser<'a>{
+        let mut stream = StreamParser::new(config);
+        stream.depth = depth;
+        stream.quiet();
+        stream
+    }
+    //for callers that only want the finished email
+    pub(crate) fn quiet(&mut self){
+        self.emit = false;
+    }
+    ///keep part bodies in the returned email, true by default.
+    pub fn retain(&mut self,v:bool){
+        self.part_handler.retain(v);
+    }
+    ///the part with the given index is handled by the caller, its data is dropped and no longer collected.
+    pub fn release(&mut self,index:usize){
+        self.part_handler.release(index);
+    }
+    ///processes a chunk and returns the events of every line completed by it, an unfinished body line is
+    ///handed out as part data as soon as it can no longer be a delimiter.
+    pub fn feed(&mut self,chunk:&[u8])->Result<Vec<Event>,&'static str>{
+        if let Some(e) = self.error{return Err(e);}
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.extend_from_slice(chunk);
+        let mut start:usize = 0;
+        while let Some(index) = buffer[start + self.scanned..].iter().position(|b|*b == b'\n'){
+            let end = start + self.scanned + index + 1;
+            let mut line = &buffer[start..end-1];
+            let mut ending = &buffer[end-1..end];
+            if line.ends_with(b"\r"){
+                line = &line[..line.len()-1];
+                ending = &buffer[end-2..end];
+            }
+            self.scanned = 0;
+            start = end;
+            if let Err(e) = self.line(line,ending){
+                self.error = Some(e);
+                return Err(e);
+            }
+        }
+        buffer.drain(..start);
+        //the unfinished last line waits for more data unless it is body data, a trailing cr may start the line ending
+        if !self.in_headers && !buffer.is_empty() && (self.continued || !boundary_prefix(&buffer,&self.frames)){
+            let keep = if buffer.ends_with(b"\r"){1} else {0};
+            let fragment:Vec<u8> = buffer.drain(..buffer.len()-keep).collect();
+            self.fragment(&fragment);
+        }
+        self.scanned = buffer.len();
+        self.buffer = buffer;
+        Ok(std::mem::take(&mut self.events))
+    }
+    ///events produced by `finish` are dropped, use `finish_events` to get them.
+    pub fn finish(self)->Result<EmailBody,&'static str>{
+        match self.finish_events(){
+            Ok((body,_))=>{Ok(body)},
+            Err(e)=>{Err(e)}
+        }
+    }
+    ///processes the last unterminated line and closes every open part, then decodes the parts.
+    pub fn finish_events(mut self)->Result<(EmailBody,Vec<Event>),&'static str>{
+
+        if let Some(e) = self.error{return Err(e);}
+
+        let last = std::mem::take(&mut self.buffer);
+        self.line(&last,b"")?;
+        self.header()?;
+
+        //without a following delimiter the last line ending belongs to the epilogue
+        if let Some(frame) = self.frames.first_mut(){
+            if frame.closed{
+                if let Some(v) = &mut frame.node.epilogue{
+                    v.extend_from_slice(&std::mem::take(&mut frame.pending));
+                }
+            }
+        }
+        self.flush_leaf();
+        self.close_frames(1);
+        if let Some(frame) = self.frames.pop(){self.root = Some(frame.node);}
+
+        let mut body = std::mem::replace(&mut self.body,EmailBody::new());
+        match self.root.take(){
+            Some(node)=>{body.tree = node;},
+            None=>{body.tree = MimeNode::new(body.content_type.clone(),None,0);}
+        }
+
+        body.line_ending = LineEnding::from_counts(self.crlf_count,self.lf_count);
+        body.addresses();
+        body.parse_date();
+        body.parse_received();
+
+        let part_handler = std::mem::take(&mut self.part_handler);
+        match body.parts_with(part_handler,self.config.lenient){
+            Ok(_)=>{
+                if self.depth < self.config.max_nesting{
+                    crate::attach_messages(&mut body,self.config,self.depth);
+                }
+                Ok((body,self.events))
+            },
+            Err(e)=>{
+                Err(e)
+            }
+        }
+
+    }
+    fn line(&mut self,line:&[u8],ending:&[u8])->Result<(),&'static str>{
+
+        let offset = self.position;
+        self.position += line.len() + ending.len();
+        if ending.len() == 2{
+            self.crlf_count += 1;
+        } else if ending.len() == 1{
+            self.lf_count += 1;
+        }
+
+        if self.continued{
+            self.continued = false;
+            self.text(line,ending,offset);
+            return Ok(());
+        }
+
+        if self.in_headers{
+
+            //folded header lines are unfolded into a single logical line
+            if line.starts_with(b" ") || line.starts_with(b"\t"){
+                if let Some(v) = &mut self.held{
+                    v.extend_from_slice(line);
+                    return Ok(());
+                }
+            }
+            self.header()?;
+
+            if line.is_empty(){//empty flag
+                self.in_headers = false;
+                if !self.in_part{
+                    self.body.body_offset = offset + ending.len();
+                }
+                let content_type = if self.in_part{
+                    self.part_handler.active().content_type.clone()
+                } else {
+                    self.body.content_type.clone()
+                };
+                let multipart = content_type.0.starts_with("multipart/");
+                let has_boundary = content_type.1.contains_key("boundary");
+                if multipart && !has_boundary{
+                    //lenient parsing keeps the entity as a single part
+                    self.fail("not_found-boundry-Content-Type")?;
+                }
+                if multipart && has_boundary{
+                    let parent = self.frames.last().map(|f|f.node.content_type.0.clone());
+                    let node = MimeNode::new(content_type,parent,self.frames.len());
+                    self.frames.push(Frame{node,closed:false,pending:Vec::new()});
+                    self.part_handler.discard();
+                    self.leaf_active = false;
+                } else {
+                    if !self.in_part{
+                        self.part_handler.content_type(content_type);
+                    }
+                    self.leaf_active = true;
+                    self.leaf_pending.clear();
+                    if self.emit{
+                        let index = self.part_handler.next_index();
+                        self.events.push(Event::PartStart(index,Box::new(self.part_handler.active().clone())));
+                    }
+                }
+                return Ok(());
+            }
+
+            self.held = Some(line.to_vec());
+            return Ok(());
+
+        }
+
+        //check if data is boundary
+        match find_boundary(line,&self.frames){
+            Some((index,close))=>{
+                self.close_frames(index + 1);
+                self.flush_leaf();
+                self.frames[index].closed = close;
+                self.frames[index].pending.clear();
+                if !close{
+                    self.in_headers = true;
+                    self.in_part = true;
+                }
+            },
+            None=>{
+                //data is not boundary
+                self.text(line,ending,offset);
+            }
+        }
+
+        Ok(())
+
+    }
+    //body data of the active part, or preamble / epilogue text when no part is active
+    fn text(&mut self,line:&[u8],ending:&[u8],offset:usize){
+        if self.leaf_active{
+            self.part_handler.data(line,ending,offset);
+            if self.emit{
+                let mut data = std::mem::take(&mut self.leaf_pending);
+                data.extend_from_slice(line);
+                if !data.is_empty(){
+                    let index = self.part_handler.next_index();
+                    self.events.push(Event::PartData(index,data));
+                }
+                self.leaf_pending = ending.to_vec();
+            }
+        } else if let Some(frame) = self.frames.last_mut(){
+            frame.text(line,ending);
+        }
+    }
+    //the start of an unfinished body line, the rest of the line follows as a continuation
+    fn fragment(&mut self,bytes:&[u8]){
+        if bytes.is_empty(){
+            return;
+        }
+        let offset = self.position;
+        self.position += bytes.len();
+        self.text(bytes,b"",offset);
+        self.continued = true;
+    }
+    //handles the held header line once it is known that no folded line follows
+    fn header(&mut self)->Result<(),&'static str>{
+
+        let held:Vec<u8> = match self.held.take(){
+            Some(v)=>{v},
+            None=>{return Ok(());}
+        };
+        let unfolded = String::from_utf8_lossy(&held);
+        let i:&str = &unfolded;
+        let (key,value) = match parser::parse_keyval(self.config,i){
+            Ok(v)=>{v},
+            Err(_)=>{
+                return self.fail("failed-parse-keyval");
+            }
+        };
+        let is_content_type = key.eq_ignore_ascii_case("Content-Type");
+
+        if !self.in_part{
+            if key.eq_ignore_ascii_case("DKIM-Signature") && !self.body.dkim_found{
+                match parser::parse_only_features(self.config,i){
+                    Ok(v)=>{
+                        self.body.dkim.overtake(v.0,v.2);
+                        self.body.dkim_found = true;
+                    },
+                    Err(_e)=>{
+                        self.fail("invalid-DKIM_Signature")?;
+                    }
+                }
+            }
+            if is_content_type{
+                match parser::parse_content_type(self.config,i){
+                    Ok(ct)=>{self.body.content_type = ct;},
+                    Err(_)=>{
+                        self.fail("failed-parse-Content-Type")?;
+                    }
+                }
+            }
+            if key.to_lowercase().starts_with("content-"){
+                self.part_handler.content_feature(key.clone(),value.clone());
+            }
+            if self.emit{
+                self.events.push(Event::Header(key.clone(),value.clone()));
+            }
+            self.body.header(key,value);
+        } else {
+            if is_content_type{
+                match parser::parse_content_type(self.config,i){
+                    Ok(ct)=>{self.part_handler.content_type(ct);},
+                    Err(_)=>{
+                        self.fail("failed-parse-Content-Type")?;
+                    }
+                }
+            }
+            self.part_handler.content_feature(key,value);
+        }
+
+        Ok(())
+
+    }
+    //in lenient mode problems are recorded as warnings and parsing goes on
+    fn fail(&mut self,e:&'static str)->Result<(),&'static str>{
+        if self.config.lenient{
+            self.body.warnings.push(e);
+            return Ok(());
+        }
+        Err(e)
+    }
+    //moves the active part into the tree as a leaf of the innermost multipart, or as the root for single part messages
+    fn flush_leaf(&mut self){
+        if !self.leaf_active{
+            return;
+        }
+        self.leaf_active = false;
+        let content_type = self.part_handler.active().content_type.clone();
+        let index = self.part_handler.finish();
+        if self.emit{
+            self.events.push(Event::PartEnd(index));
+        }
+        match self.frames.last_mut(){
+            Some(frame)=>{
+                let mut node = MimeNode::new(content_type,Some(frame.node.content_type.0.clone()),frame.node.depth + 1);
+                node.index = Some(index);
+                frame.node.children.push(node);
+            },
+            None=>{
+                let mut node = MimeNode::new(content_type,None,0);
+                node.index = Some(index);
+                self.root = Some(node);
+            }
+        }
+    }
+    //closes every frame above the given stack length, each closed frame becomes a child of its parent
+    fn close_frames(&mut self,keep:usize){
+        while self.frames.len() > keep{
+            self.flush_leaf();
+            if let Some(frame) = self.frames.pop(){
+                if let Some(parent) = self.frames.last_mut(){parent.node.children.push(frame.node);}
+            }
+        }
+    }
+}
+
+//an open multipart entity, closed frames stay on the stack collecting their epilogue until a boundary of an outer frame shows up
+struct Frame{
+    node:MimeNode,
+    closed:bool,
+    pending:Vec<u8>
+}
+
+impl Frame{
+    //preamble or epilogue text, the line ending before a delimiter belongs to the delimiter
+    fn text(&mut self,line:&[u8],ending:&[u8]){
+        let target = if self.closed{
+            &mut self.node.epilogue
+        } else {
+            &mut self.node.preamble
+        };
+        match target{
+            Some(v)=>{
+                v.extend_from_slice(&self.pending);
+                v.extend_from_slice(line);
+            },
+            None=>{
+                *target = Some(line.to_vec());
+            }
+        }
+        self.pending = ending.to_vec();
+    }
+}
+
+//the longest line rfc 5322 allows, delimiters with more transport padding are not recognized
+const MAX_LINE:usize = 998;
+
+//a delimiter line is "--" followed by one of the active boundaries and optional transport padding,
+//the close delimiter adds a trailing "--", outer boundaries are checked too so unclosed parts get closed
+fn find_boundary(line:&[u8],frames:&[Frame])->Option<(usize,bool)>{
+    if !line.starts_with(b"--") || line.len() > MAX_LINE{
+        return None;
+    }
+    let mut rest = &line[2..];
+    while let Some(last) = rest.last(){
+        if *last == b' ' || *last == b'\t' || *last == b'\r'{
+            rest = &rest[..rest.len()-1];
+        } else {
+            break;
+        }
+    }
+    for (index,frame) in frames.iter().enumerate().rev(){
+        let boundary:&[u8] = match &frame.node.boundary{
+            Some(b)=>{b.as_bytes()},
+            None=>{continue;}
+        };
+        if rest == boundary{
+            return Some((index,false));
+        }
+        if rest.len() == boundary.len() + 2 && rest.starts_with(boundary) && rest.ends_with(b"--"){
+            return Some((index,true));
+        }
+    }
+    None
+}
+
+//true while an unfinished line can still turn out to be a delimiter of one of the frames
+fn boundary_prefix(partial:&[u8],frames:&[Frame])->bool{
+    if partial.len() < 2{
+        return b"--".starts_with(partial);
+    }
+    if !partial.starts_with(b"--") || partial.len() > MAX_LINE{
+        return false;
+    }
+    let rest = &partial[2..];
+    for frame in frames.iter(){
+        let boundary:&[u8] = match &frame.node.boundary{
+            Some(b)=>{b.as_bytes()},
+            None=>{continue;}
+        };
+        if rest.len() <= boundary.len(){
+            if boundary.starts_with(rest){
+                return true;
+            }
+            continue;
+        }
+        if !rest.starts_with(boundary){
+            continue;
+        }
+        let mut tail = &rest[boundary.len()..];
+        if tail == b"-"{
+            return true;
+        }
+        if tail.starts_with(b"--"){
+            tail = &tail[2..];
+        }
+        if tail.iter().all(|b|*b == b' ' || *b == b'\t' || *b == b'\r'){
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::{StreamParser,Event};
+    use crate::{Config,EmailBody,parse_bytes,ContentDecoded,io};
+    use std::collections::HashMap;
+
+    fn text(decoded:&ContentDecoded)->&str{
+        match decoded{
+            ContentDecoded::String(v)|ContentDecoded::Html(v)=>{v},
+            _=>{""}
+        }
+    }
+
+    #[test]
+    fn exact_boundaries(){
+        let raw = "Content-Type: multipart/mixed; boundary=\"a'(b)+_,-./:=? c\"\r\n\r\n--a'(b)+_,-./:=? c \t\r\nContent-Type: text/plain\r\n\r\n-- \r\nsignature\r\n--abc\r\n<!-- comment -->\r\n--a'(b)+_,-./:=? c--\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        assert_eq!(email.body.len(),1);
+        assert_eq!(text(&email.body[0].decoded),"-- \nsignature\n--abc\n<!-- comment -->");
+    }
+
+    #[test]
+    fn nested_boundaries(){
+        //the inner multipart is never closed, the outer delimiter closes it
+        let raw = "Content-Type: multipart/mixed; boundary=outer\r\n\r\n--outer\r\nContent-Type: multipart/alternative; boundary=inner\r\n\r\n--inner\r\nContent-Type: text/plain\r\n\r\nplain\r\n--inner\r\nContent-Type: text/html\r\n\r\n<p>html</p>\r\n--outer\r\nContent-Type: text/plain\r\n\r\nlast\r\n--outer--\r\n";
+        let email = parse_bytes(raw.as_bytes(),&Config::new().unwrap()).unwrap();
+        let bodies:Vec<&str> = email.body.iter().map(|p|text(&p.decoded)).collect();
+        assert_eq!(bodies,vec!["plain","<p>html</p>","last"]);
+        assert_eq!(email.tree.children.len(),2);
+        assert_eq!(email.tree.children[0].children.len(),2);
+    }
+
+    //the email and the concatenated part data events of an input fed in chunks of the given size
+    fn chunked(input:&[u8],size:usize,config:&Config)->(EmailBody,HashMap<usize,Vec<u8>>){
+        let mut stream = StreamParser::new(config);
+        let mut data:HashMap<usize,Vec<u8>> = HashMap::new();
+        let mut events = vec![];
+        for chunk in input.chunks(size){
+            events.extend(stream.feed(chunk).unwrap());
+        }
+        let (email,rest) = stream.finish_events().unwrap();
+        events.extend(rest);
+        for event in events{
+            if let Event::PartData(index,v) = event{
+                data.entry(index).or_default().extend_from_slice(&v);
+            }
+        }
+        (email,data)
+    }
+
+    fn summary(email:&EmailBody)->String{
+        let parts:Vec<String> = email.body.iter().chain(email.attachments.iter()).map(|p|{
+            format!("{:?} {:?} {:?} {:?}",p.content_type.0,p.offsets,p.decoded,p.digest())
+        }).collect();
+        format!("{:?} {:?} {:?} {:?} {:?} {:?}",email.header_list,parts,email.tree.preamble,email.tree.epilogue,email.body_offset,email.line_ending)
+    }
+
+    #[test]
+    fn chunked_parity(){
+        let config = Config::new().unwrap();
+        let mut inputs = vec![];
+        for path in ["./sldv_atch.txt","./gl_alt_atch.txt","./ge_html.txt"]{
+            inputs.push(io::read_file(path).unwrap());
+        }
+        inputs.push(b"Content-Type: multipart/mixed; boundary=b\n\npre\r\n--b  \r\nContent-Type: text/plain\r\n\n--bx\r\n--b-\n-\r\n--b--\r\nepi".to_vec());
+        for input in inputs.iter(){
+            let (whole,whole_data) = chunked(input,input.len(),&config);
+            for size in [1,2,3,7,64,4096]{
+                let (email,data) = chunked(input,size,&config);
+                assert_eq!(summary(&email),summary(&whole));
+                assert_eq!(data,whole_data);
+            }
+            assert_eq!(summary(&whole),summary(&parse_bytes(input,&config).unwrap()));
+        }
+    }
+
+    #[test]
+    fn long_lines_are_not_buffered(){
+        let config = Config::new().unwrap();
+        let mut stream = StreamParser::new(&config);
+        stream.feed(b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: application/octet-stream\r\n\r\n").unwrap();
+        let mut size = 0;
+        for _ in 0..1000{
+            for event in stream.feed(&[b'a';1000]).unwrap(){
+                if let Event::PartData(_,v) = event{size += v.len();}
+            }
+            assert!(stream.buffer.len() < 100);
+        }
+        //a possible delimiter is held back until it is complete
+        stream.feed(b"\r\n--").unwrap();
+        assert_eq!(stream.buffer,b"--");
+        stream.feed(b"b--\r\n").unwrap();
+        let email = stream.finish().unwrap();
+        assert_eq!(size,1000 * 1000);
+        assert_eq!(email.attachments[0].bytes().map(|v|v.len()),Some(1000 * 1000));
+    }
+
+}